@@ -0,0 +1,120 @@
+//! Games per second in the simulation hot loop: single rolls, whole games, and whole parallel
+//! runs, with each of the random number generators we might play them with.
+//!
+//! Criterion keeps every run's results under `target/criterion`; save a baseline with
+//! `cargo bench -- --save-baseline NAME` and compare a later commit with `-- --baseline NAME`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::rngs::{SmallRng, StdRng};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rand_pcg::Pcg64Mcg;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use orchard::progress::Silent;
+use orchard::rules::Rules;
+use orchard::simulate::{estimate_win_rate, CHUNK_GAMES};
+use orchard::strategy::{BasketStrategy, LargestFirst, Random};
+use orchard::{Game, Outcome};
+
+/// Runs `bench` once with each generator, named as it should appear in the report.
+fn with_each_rng(mut bench: impl FnMut(&str, Box<dyn Bench>)) {
+    bench("thread", Box::new(thread_rng()));
+    bench("std", Box::new(StdRng::seed_from_u64(1)));
+    // What the simulator itself plays with, one stream per chunk.
+    bench("chacha12", Box::new(ChaCha12Rng::seed_from_u64(1)));
+    bench("small", Box::new(SmallRng::seed_from_u64(1)));
+    bench("pcg64mcg", Box::new(Pcg64Mcg::seed_from_u64(1)));
+    bench(
+        "xoshiro256++",
+        Box::new(Xoshiro256PlusPlus::seed_from_u64(1)),
+    );
+}
+
+/// The loops being measured, for one concrete generator, so that it's monomorphized into them
+/// just as it would be in the simulator.
+trait Bench {
+    /// Plays one roll of a game in progress, starting a new game once it's over.
+    fn roll(&mut self, rules: &Rules, strategy: &dyn BasketStrategy, game: &mut Game);
+
+    fn full_game(&mut self, rules: &Rules, strategy: &dyn BasketStrategy) -> (Outcome, u32);
+}
+
+impl<R: Rng> Bench for R {
+    fn roll(&mut self, rules: &Rules, strategy: &dyn BasketStrategy, game: &mut Game) {
+        let roll = self.sample(rules);
+        if game.apply(rules, roll, strategy, self).is_some() {
+            *game = Game::new(rules);
+        }
+    }
+
+    fn full_game(&mut self, rules: &Rules, strategy: &dyn BasketStrategy) -> (Outcome, u32) {
+        Game::full_game(rules, strategy, self)
+    }
+}
+
+fn rules() -> [(&'static str, Rules); 2] {
+    [
+        ("first-orchard", Rules::default()),
+        ("orchard", Rules::orchard()),
+    ]
+}
+
+fn apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply");
+    group.throughput(Throughput::Elements(1));
+    for (edition, rules) in rules() {
+        with_each_rng(|name, mut rng| {
+            let mut game = Game::new(&rules);
+            group.bench_function(BenchmarkId::new(edition, name), |b| {
+                b.iter(|| rng.roll(&rules, &LargestFirst, &mut game))
+            });
+        });
+    }
+    group.finish();
+}
+
+fn full_game(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full_game");
+    group.throughput(Throughput::Elements(1));
+    let strategies: [(&str, &dyn BasketStrategy); 2] =
+        [("largest", &LargestFirst), ("random", &Random)];
+    for (edition, rules) in rules() {
+        for (strategy_name, strategy) in strategies {
+            with_each_rng(|name, mut rng| {
+                let id = BenchmarkId::new(format!("{edition}/{strategy_name}"), name);
+                group.bench_function(id, |b| b.iter(|| rng.full_game(&rules, strategy)));
+            });
+        }
+    }
+    group.finish();
+}
+
+fn estimate(c: &mut Criterion) {
+    const GAMES: u64 = 64 * CHUNK_GAMES;
+    let mut group = c.benchmark_group("estimate_win_rate");
+    group.throughput(Throughput::Elements(GAMES));
+    group.sample_size(10);
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    let mut threads = vec![1, 2, 4, 8, cores];
+    threads.sort_unstable();
+    threads.dedup();
+    for (edition, rules) in rules() {
+        for &threads in &threads {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let id = BenchmarkId::new(edition, format!("{threads} threads"));
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    pool.install(|| estimate_win_rate(&rules, &LargestFirst, GAMES, 1, &Silent))
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, apply, full_game, estimate);
+criterion_main!(benches);
@@ -0,0 +1,431 @@
+//! The competitive house rule: each player keeps the fruit they pick, and if the team beats the
+//! bird, whoever holds the most fruit wins.
+//!
+//! The exact solver works much like `solver`, with each state also carrying every player's
+//! holdings and whose turn it is. A roll that changes nothing still passes the die on, so what
+//! were self-loops become cycles once round the table; each state's values for every seat are
+//! solved together, in closed form.
+//!
+//! Only the differences between holdings matter, and a player further behind the leader than
+//! there is fruit left can never catch up, so holdings are stored relative to the leader's, with
+//! anyone out of contention counted as holding nothing.
+
+use std::collections::HashMap;
+use std::ops::AddAssign;
+use std::ops::Range;
+
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Num};
+use rand::Rng;
+
+use crate::progress::Progress;
+use crate::rules::Rules;
+use crate::simulate::play_chunks_from;
+use crate::strategy::BasketStrategy;
+use crate::{DieRoll, Game, Outcome};
+
+/// How a competitive game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finish {
+    /// The bird won, so nobody did.
+    Lost,
+    /// The team won, and this seat held the most fruit.
+    Winner(usize),
+    /// The team won, but two or more players shared the most fruit.
+    Tie,
+}
+
+/// Who finished on top, given each player's fruit at the end of a game the team won.
+fn finish(holdings: &[u16]) -> Finish {
+    // Unwrap: there is always at least one player.
+    let most = *holdings.iter().max().unwrap();
+    let mut leaders = (0..holdings.len()).filter(|&seat| holdings[seat] == most);
+    match (leaders.next(), leaders.next()) {
+        (Some(seat), None) => Finish::Winner(seat),
+        _ => Finish::Tie,
+    }
+}
+
+fn fruit_left(game: &Game) -> u16 {
+    game.orchards.iter().map(|&apples| u16::from(apples)).sum()
+}
+
+impl Game {
+    /// Plays a competitive game to the end, with the players taking turns in seat order, each
+    /// keeping whatever fruit their own rolls pick.
+    pub fn competitive_game(
+        rules: &Rules,
+        players: &[&dyn BasketStrategy],
+        rng: &mut impl Rng,
+    ) -> Finish {
+        assert!(!players.is_empty(), "a game needs at least one player");
+        let mut game = Game::new(rules);
+        let mut holdings = vec![0; players.len()];
+        for seat in (0..players.len()).cycle() {
+            let before = fruit_left(&game);
+            let roll = rng.sample(rules);
+            let outcome = game.apply(rules, roll, players[seat], rng);
+            holdings[seat] += before - fruit_left(&game);
+            match outcome {
+                Some(Outcome::Won) => return finish(&holdings),
+                Some(Outcome::Lost) => return Finish::Lost,
+                None => {}
+            }
+        }
+        unreachable!("cycling through a non-empty list of seats never ends")
+    }
+}
+
+/// How a batch of competitive games came out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompetitionTally {
+    pub lost: u64,
+    pub ties: u64,
+    /// Games each seat won outright.
+    pub wins: Vec<u64>,
+}
+
+impl CompetitionTally {
+    /// No games yet, between `seats` players.
+    pub fn new(seats: usize) -> CompetitionTally {
+        CompetitionTally {
+            wins: vec![0; seats],
+            ..CompetitionTally::default()
+        }
+    }
+
+    pub fn record(mut self, finish: Finish) -> CompetitionTally {
+        match finish {
+            Finish::Lost => self.lost += 1,
+            Finish::Winner(seat) => self.wins[seat] += 1,
+            Finish::Tie => self.ties += 1,
+        }
+        self
+    }
+
+    pub fn merge(mut self, other: CompetitionTally) -> CompetitionTally {
+        for (wins, other) in self.wins.iter_mut().zip(other.wins) {
+            *wins += other;
+        }
+        self.lost += other.lost;
+        self.ties += other.ties;
+        self
+    }
+
+    pub fn games(&self) -> u64 {
+        self.lost + self.ties + self.wins.iter().sum::<u64>()
+    }
+}
+
+/// Plays the competitive games numbered `games`, like `simulate::play_games`.
+pub fn play_competitive_games(
+    rules: &Rules,
+    players: &[&dyn BasketStrategy],
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+) -> CompetitionTally {
+    play_chunks_from(
+        games,
+        seed,
+        progress,
+        || CompetitionTally::new(players.len()),
+        |tally: CompetitionTally, rng| tally.record(Game::competitive_game(rules, players, rng)),
+        CompetitionTally::merge,
+    )
+}
+
+/// Number types the solver can work in: exact fractions, or floating point when there are too many
+/// states for fractions to be quick.
+pub trait Probability: Clone + Num + FromPrimitive + for<'x> AddAssign<&'x Self> {}
+
+impl<P: Clone + Num + FromPrimitive + for<'x> AddAssign<&'x P>> Probability for P {}
+
+fn count<P: Probability>(n: usize) -> P {
+    // Unwrap: die faces and basket choices are few enough for any number type.
+    P::from_usize(n).unwrap()
+}
+
+/// Exact chances of each competitive finish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompetitionSolution<P = BigRational> {
+    /// Probability that each seat wins outright.
+    pub wins: Vec<P>,
+    pub tie: P,
+}
+
+impl<P: Probability> CompetitionSolution<P> {
+    pub fn lost(&self) -> P {
+        let won = self
+            .wins
+            .iter()
+            .fold(self.tie.clone(), |won, wins| won + wins.clone());
+        P::one() - won
+    }
+}
+
+/// Chances of each seat winning, then of a tie: the same shape as `CompetitionSolution`, but
+/// easier to do sums with.
+type Chances<P> = Vec<P>;
+
+/// Solves competitive games, memoizing every state's values.
+///
+/// `P` is `BigRational` for exact answers; `f64` gets the same answers (give or take rounding) far
+/// more quickly once there are several players and plenty of fruit.
+pub struct CompetitionSolver<'a, P = BigRational> {
+    rules: &'a Rules,
+    players: &'a [&'a dyn BasketStrategy],
+    /// Whether every player treats colors alike, so that games can be solved in canonical form.
+    symmetric: bool,
+    /// Chances from a game and everyone's (relative) holdings, for each seat to move.
+    memo: HashMap<(Game, Vec<u16>), Vec<Chances<P>>>,
+}
+
+impl<'a, P: Probability> CompetitionSolver<'a, P> {
+    pub fn new(
+        rules: &'a Rules,
+        players: &'a [&'a dyn BasketStrategy],
+    ) -> CompetitionSolver<'a, P> {
+        assert!(!players.is_empty(), "a game needs at least one player");
+        CompetitionSolver {
+            rules,
+            players,
+            symmetric: rules.colors_alike() && players.iter().all(|player| player.is_symmetric()),
+            memo: HashMap::new(),
+        }
+    }
+
+    /// Solves the game from the start the rules describe, with the first seat to roll.
+    pub fn solve(&mut self) -> CompetitionSolution<P> {
+        let start = Game::new(self.rules);
+        let mut chances = self.state_chances(start, vec![0; self.players.len()], 0);
+        // Unwrap: chances always end with the tie.
+        let tie = chances.pop().unwrap();
+        CompetitionSolution { wins: chances, tie }
+    }
+
+    fn seats(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Holdings that lead to the same finishes as `holdings` from `game`, with the leader holding
+    /// one more than the fruit left, and anyone who can no longer catch up holding none.
+    fn relative(game: &Game, holdings: &[u16]) -> Vec<u16> {
+        // Unwrap: there is always at least one player.
+        let most = *holdings.iter().max().unwrap();
+        let lead = fruit_left(game) + 1;
+        holdings
+            .iter()
+            .map(|&held| (held + lead).saturating_sub(most))
+            .collect()
+    }
+
+    /// Chances for a finished game, given what everyone holds.
+    fn finished(&self, holdings: &[u16], outcome: Outcome) -> Chances<P> {
+        let mut chances = vec![P::zero(); self.seats() + 1];
+        match outcome {
+            Outcome::Lost => {}
+            Outcome::Won => match finish(holdings) {
+                Finish::Winner(seat) => chances[seat] = P::one(),
+                _ => chances[self.seats()] = P::one(),
+            },
+        }
+        chances
+    }
+
+    /// Chances from an unfinished `game`, with `seat` to roll.
+    fn state_chances(&mut self, game: Game, holdings: Vec<u16>, seat: usize) -> Chances<P> {
+        // Holdings are per seat rather than per color, so relabelling colors leaves them alone.
+        let game = if self.symmetric {
+            game.canonical()
+        } else {
+            game
+        };
+        let key = (game, Self::relative(&game, &holdings));
+        if let Some(chances) = self.memo.get(&key) {
+            return chances[seat].clone();
+        }
+
+        let seats = self.seats();
+        let still_weight: u32 = self
+            .rules
+            .die()
+            .filter(
+                |&(roll, _)| matches!(roll, DieRoll::Fruit(orchard) if game.orchards[orchard] == 0),
+            )
+            .map(|(_, weight)| weight)
+            .sum();
+        // What each seat's roll leads to, summed over the faces that change the game.
+        let mut moving = Vec::with_capacity(seats);
+        for roller in 0..seats {
+            let after = (roller + 1) % seats;
+            let mut total = vec![P::zero(); seats + 1];
+            for (roll, weight) in self.rules.die() {
+                let mut next = game;
+                let mut held = key.1.clone();
+                let chances = match roll {
+                    DieRoll::Basket => {
+                        self.basket_chances(game, held, roller, self.rules.basket_picks)
+                    }
+                    DieRoll::Bird => {
+                        let outcome = next.move_bird();
+                        self.chances(next, held, outcome, after)
+                    }
+                    DieRoll::Fruit(orchard) => {
+                        let outcome = next.pick(orchard);
+                        if next == game {
+                            continue;
+                        }
+                        held[roller] += 1;
+                        self.chances(next, held, outcome, after)
+                    }
+                };
+                add(&mut total, &scaled(&chances, &count(weight as usize)));
+            }
+            moving.push(total);
+        }
+
+        // With `q` the chance of a roll changing nothing, each seat's chances are `V[t] = a[t] +
+        // q V[t + 1]`, where `a[t]` is what the rolls that do change something contribute.
+        // Going once round the table gives `V[0] = (a[0] + q a[1] + ... + q^(n-1) a[n-1]) /
+        // (1 - q^n)`, and the rest follow.
+        let total_weight: P = count(self.rules.total_weight() as usize);
+        let q = count::<P>(still_weight as usize) / total_weight.clone();
+        let a: Vec<Chances<P>> = moving
+            .into_iter()
+            .map(|total| scaled(&total, &(P::one() / total_weight.clone())))
+            .collect();
+        let mut first = vec![P::zero(); seats + 1];
+        let mut power = P::one();
+        for contribution in &a {
+            add(&mut first, &scaled(contribution, &power));
+            power = power * q.clone();
+        }
+        let first = scaled(&first, &(P::one() / (P::one() - power)));
+
+        let mut values = vec![first.clone(); seats];
+        for roller in (1..seats).rev() {
+            let mut value = a[roller].clone();
+            add(&mut value, &scaled(&values[(roller + 1) % seats], &q));
+            values[roller] = value;
+        }
+        let chances = values[seat].clone();
+        self.memo.insert(key, values);
+        chances
+    }
+
+    /// Chances with `picks_left` fruit still to take from the basket that `seat` rolled.
+    fn basket_chances(
+        &mut self,
+        game: Game,
+        holdings: Vec<u16>,
+        seat: usize,
+        picks_left: u8,
+    ) -> Chances<P> {
+        let choices = self.players[seat].choices(&game, picks_left);
+        let mut total = vec![P::zero(); self.seats() + 1];
+        for &orchard in &choices {
+            let mut next = game;
+            let outcome = next.pick(orchard);
+            let mut held = holdings.clone();
+            held[seat] += 1;
+            let chances = match outcome {
+                None if picks_left > 1 => self.basket_chances(next, held, seat, picks_left - 1),
+                _ => self.chances(next, held, outcome, (seat + 1) % self.seats()),
+            };
+            add(&mut total, &chances);
+        }
+        scaled(&total, &(P::one() / count(choices.len())))
+    }
+
+    /// Chances from having just arrived at `game`, which may have ended it.
+    fn chances(
+        &mut self,
+        game: Game,
+        holdings: Vec<u16>,
+        outcome: Option<Outcome>,
+        seat: usize,
+    ) -> Chances<P> {
+        match outcome {
+            Some(outcome) => self.finished(&holdings, outcome),
+            None => self.state_chances(game, holdings, seat),
+        }
+    }
+}
+
+fn add<P: Probability>(total: &mut Chances<P>, other: &Chances<P>) {
+    for (total, other) in total.iter_mut().zip(other) {
+        *total += other;
+    }
+}
+
+fn scaled<P: Probability>(chances: &Chances<P>, factor: &P) -> Chances<P> {
+    chances
+        .iter()
+        .map(|chance| chance.clone() * factor.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+    use crate::solver::Solver;
+    use crate::strategy::{LargestFirst, Random, SmallestFirst};
+    use num_traits::{One, Zero};
+
+    fn small_rules() -> Rules {
+        Rules {
+            apples: 2,
+            colors: 3,
+            track_length: 4,
+            ..Rules::default()
+        }
+    }
+
+    #[test]
+    fn one_player_always_wins_alone() {
+        let rules = small_rules();
+        let players: [&dyn BasketStrategy; 1] = [&LargestFirst];
+        let solution: CompetitionSolution = CompetitionSolver::new(&rules, &players).solve();
+        let team = Solver::new(&rules, &LargestFirst).solve().win_probability;
+        assert_eq!(solution.wins, [team]);
+        assert!(solution.tie.is_zero());
+    }
+
+    #[test]
+    fn team_win_rate_is_shared_out() {
+        let rules = small_rules();
+        let players: [&dyn BasketStrategy; 3] = [&LargestFirst, &LargestFirst, &LargestFirst];
+        let solution: CompetitionSolution = CompetitionSolver::new(&rules, &players).solve();
+        let team = Solver::new(&rules, &LargestFirst).solve().win_probability;
+        assert_eq!(BigRational::one() - solution.lost(), team);
+        // Rolling first is an advantage.
+        assert!(solution.wins[0] > solution.wins[2]);
+    }
+
+    #[test]
+    fn simulation_matches_exact() {
+        let rules = small_rules();
+        let players: [&dyn BasketStrategy; 2] = [&SmallestFirst, &Random];
+        let exact: CompetitionSolution<f64> = CompetitionSolver::new(&rules, &players).solve();
+        let n = 100_000;
+        let tally = play_competitive_games(&rules, &players, 0..n, 3, &Silent);
+        assert_eq!(tally.games(), n);
+        // Every seat has a count, even before any games are played.
+        let none = play_competitive_games(&rules, &players, 0..0, 3, &Silent);
+        assert_eq!(none.wins, [0, 0]);
+
+        let observed = tally
+            .wins
+            .iter()
+            .chain([&tally.ties])
+            .map(|&count| count as f64 / n as f64);
+        let expected = exact.wins.iter().chain([&exact.tie]);
+        for (observed, expected) in observed.zip(expected) {
+            let p = *expected;
+            let sigma = (p * (1.0 - p) / n as f64).sqrt();
+            assert!((observed - p).abs() < 4.0 * sigma, "{observed} vs {p}");
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Real dice, which may not be fair: reading a log of rolls made at the table, and fitting the
+//! die's face weights to it.
+//!
+//! A roll log is just the names of what the die showed ("red", "basket", "bird" and so on), in any
+//! case, separated by whitespace or commas; anything after a `#` is a comment.
+//!
+//! [`FairnessTest`] checks a log against the die the rules describe, with Pearson's chi-square test
+//! and the G (likelihood ratio) test.
+
+use std::io::{self, BufRead};
+
+use crate::rules::Rules;
+use crate::stats::{chi_square_p_value, Interval};
+use crate::{DieRoll, COLOR_NAMES};
+
+/// The roll a face's name describes, if the die in `rules` has such a face.
+pub fn parse_roll(rules: &Rules, name: &str) -> Result<DieRoll, String> {
+    let name = name.to_lowercase();
+    let roll = match name.as_str() {
+        "basket" if rules.basket_faces > 0 => DieRoll::Basket,
+        "bird" if rules.bird_faces > 0 => DieRoll::Bird,
+        _ => COLOR_NAMES[..usize::from(rules.colors)]
+            .iter()
+            .position(|&color| color == name)
+            .map(DieRoll::Fruit)
+            .ok_or_else(|| format!("the die has no '{name}' face"))?,
+    };
+    Ok(roll)
+}
+
+/// Reads a roll log; anything that isn't a face of the die is an `InvalidData` error giving the
+/// line it was on.
+pub fn read_rolls(input: impl BufRead, rules: &Rules) -> io::Result<Vec<DieRoll>> {
+    let mut rolls = Vec::new();
+    for (number, line) in (1..).zip(input.lines()) {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default();
+        for name in line.split(|c: char| c == ',' || c.is_whitespace()) {
+            if name.is_empty() {
+                continue;
+            }
+            let roll = parse_roll(rules, name).map_err(|message| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {number}: {message}"),
+                )
+            })?;
+            rolls.push(roll);
+        }
+    }
+    Ok(rolls)
+}
+
+/// Face weights (see `Rules::face_weights`) for a die that rolled `rolls`.
+///
+/// Each face starts with one roll's worth of weight, so that faces that never came up in a short
+/// log aren't taken to be impossible. The basket and bird faces can't be told apart from others
+/// of their kind, so their rolls are shared out evenly between them.
+pub fn fit_weights(rules: &Rules, rolls: &[DieRoll]) -> Result<Vec<u32>, String> {
+    let mut fruit = vec![1u64; usize::from(rules.colors)];
+    let mut baskets = u64::from(rules.basket_faces);
+    let mut birds = u64::from(rules.bird_faces);
+    for &roll in rolls {
+        match roll {
+            DieRoll::Fruit(orchard) if orchard < fruit.len() => fruit[orchard] += 1,
+            DieRoll::Basket if rules.basket_faces > 0 => baskets += 1,
+            DieRoll::Bird if rules.bird_faces > 0 => birds += 1,
+            _ => return Err(format!("the die can't roll {roll:?}")),
+        }
+    }
+
+    // Scale everything by the number of basket and bird faces, so the shares come out whole.
+    let basket_faces = u64::from(rules.basket_faces.max(1));
+    let bird_faces = u64::from(rules.bird_faces.max(1));
+    let mut weights: Vec<u64> = fruit
+        .iter()
+        .map(|count| count * basket_faces * bird_faces)
+        .collect();
+    weights.extend((0..rules.basket_faces).map(|_| baskets * bird_faces));
+    weights.extend((0..rules.bird_faces).map(|_| birds * basket_faces));
+
+    let divisor = weights.iter().copied().fold(0, gcd);
+    if weights.iter().sum::<u64>() / divisor > u64::from(u32::MAX) {
+        return Err("too many rolls to fit the die's weights to".to_owned());
+    }
+    // Unwrap: each weight is at most their sum, just checked to fit.
+    Ok(weights
+        .into_iter()
+        .map(|weight| u32::try_from(weight / divisor).unwrap())
+        .collect())
+}
+
+/// How often the die showed one kind of roll, against how often it should have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollCount {
+    pub roll: DieRoll,
+    /// Faces of the die that show this roll.
+    pub faces: u8,
+    pub observed: u64,
+    /// Mean count for the die the rules describe.
+    pub expected: f64,
+}
+
+/// Goodness-of-fit tests of a roll log against the die the rules describe (a fair one, unless they
+/// give face weights).
+///
+/// Faces that show the same roll can't be told apart in a log, so the tests compare counts of each
+/// kind of roll rather than of each face.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FairnessTest {
+    pub rolls: u64,
+    /// Every kind of roll the die can show: fruit colors in orchard order, then basket and bird.
+    pub counts: Vec<RollCount>,
+    /// Pearson's statistic, the sum of `(observed - expected)^2 / expected`.
+    pub chi_square: f64,
+    /// The likelihood ratio statistic, `2 * sum(observed * ln(observed / expected))`.
+    pub g: f64,
+    pub degrees_of_freedom: u32,
+}
+
+impl FairnessTest {
+    pub fn new(rules: &Rules, rolls: &[DieRoll]) -> Result<FairnessTest, String> {
+        let mut counts: Vec<RollCount> = (0..rules.colors)
+            .map(|face| (rules.face(face), 1))
+            .chain((rules.basket_faces > 0).then_some((DieRoll::Basket, rules.basket_faces)))
+            .chain((rules.bird_faces > 0).then_some((DieRoll::Bird, rules.bird_faces)))
+            .map(|(roll, faces)| RollCount {
+                roll,
+                faces,
+                observed: 0,
+                expected: 0.0,
+            })
+            .collect();
+        if counts.len() < 2 {
+            return Err("a die that only shows one thing can't be unfair".to_owned());
+        }
+        if rolls.is_empty() {
+            return Err("there are no rolls to test".to_owned());
+        }
+        for &roll in rolls {
+            match counts.iter_mut().find(|count| count.roll == roll) {
+                Some(count) => count.observed += 1,
+                None => return Err(format!("the die can't roll {roll:?}")),
+            }
+        }
+
+        let total_weight = f64::from(rules.total_weight());
+        let n = rolls.len() as f64;
+        for (face, roll) in (0..rules.faces()).map(|face| (face, rules.face(face))) {
+            // Unwrap: every face's roll has a count.
+            let count = counts.iter_mut().find(|count| count.roll == roll).unwrap();
+            count.expected += n * f64::from(rules.weight(face)) / total_weight;
+        }
+        let chi_square = counts
+            .iter()
+            .map(|count| (count.observed as f64 - count.expected).powi(2) / count.expected)
+            .sum();
+        let g = 2.0
+            * counts
+                .iter()
+                .filter(|count| count.observed > 0)
+                .map(|count| count.observed as f64 * (count.observed as f64 / count.expected).ln())
+                .sum::<f64>();
+        Ok(FairnessTest {
+            rolls: n as u64,
+            degrees_of_freedom: counts.len() as u32 - 1,
+            counts,
+            chi_square,
+            g,
+        })
+    }
+
+    /// The chance of a chi-square statistic at least this large from the die the rules describe.
+    pub fn chi_square_p_value(&self) -> f64 {
+        chi_square_p_value(self.chi_square, self.degrees_of_freedom)
+    }
+
+    /// The chance of a G statistic at least this large from the die the rules describe.
+    pub fn g_p_value(&self) -> f64 {
+        chi_square_p_value(self.g, self.degrees_of_freedom)
+    }
+
+    /// Whether some roll was expected fewer than five times, which is too few for the p-values'
+    /// chi-square approximation to be trusted.
+    pub fn too_few_rolls(&self) -> bool {
+        self.counts.iter().any(|count| count.expected < 5.0)
+    }
+
+    /// The measured probability of each face showing each kind of roll, with its (Wilson)
+    /// confidence interval.
+    pub fn face_probabilities(&self, confidence: f64) -> Vec<(DieRoll, f64, Interval)> {
+        self.counts
+            .iter()
+            .map(|count| {
+                let faces = f64::from(count.faces);
+                let interval = Interval::wilson(count.observed, self.rolls, confidence);
+                let per_face = Interval {
+                    low: interval.low / faces,
+                    high: interval.high / faces,
+                };
+                let probability = count.observed as f64 / self.rolls as f64 / faces;
+                (count.roll, probability, per_face)
+            })
+            .collect()
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_roll_log() {
+        let log =
+            "# a game on the kitchen table\nRed, green bird\n\nbasket  red # and then\nyellow\n";
+        let rolls = read_rolls(log.as_bytes(), &Rules::default()).unwrap();
+        assert_eq!(
+            rolls,
+            [
+                DieRoll::Fruit(0),
+                DieRoll::Fruit(1),
+                DieRoll::Bird,
+                DieRoll::Basket,
+                DieRoll::Fruit(0),
+                DieRoll::Fruit(3),
+            ]
+        );
+
+        let error = read_rolls("red\npurple\n".as_bytes(), &Rules::default()).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"), "{error}");
+        let no_basket = Rules {
+            basket_faces: 0,
+            ..Rules::default()
+        };
+        assert!(parse_roll(&no_basket, "basket").is_err());
+    }
+
+    #[test]
+    fn fits_weights_with_a_roll_for_every_face() {
+        let rules = Rules::default();
+        // Counts of 3, 1, 1, 1, 0 and 2, plus one each.
+        let rolls = [0, 0, 0, 1, 2, 3, 5, 5].map(|face| rules.face(face));
+        assert_eq!(fit_weights(&rules, &rolls), Ok(vec![4, 2, 2, 2, 1, 3]));
+        // With no rolls at all, the die is taken to be fair.
+        assert_eq!(fit_weights(&rules, &[]), Ok(vec![1; 6]));
+
+        // Two basket faces share their three rolls (plus one each) between them; weights are
+        // doubled to keep them whole.
+        let rules = Rules {
+            basket_faces: 2,
+            ..Rules::default()
+        };
+        let rolls = [DieRoll::Basket; 3];
+        assert_eq!(fit_weights(&rules, &rolls), Ok(vec![2, 2, 2, 2, 5, 5, 2]));
+        let fitted = Rules {
+            face_weights: fit_weights(&rules, &rolls).unwrap(),
+            ..rules
+        };
+        assert_eq!(fitted.validate(), Ok(()));
+    }
+
+    #[test]
+    fn fairness_tests() {
+        let rules = Rules::default();
+        // Each face 10 times, bar red 20 and the bird not at all.
+        let mut rolls = Vec::new();
+        for (face, times) in (0..6).zip([20, 10, 10, 10, 10, 0]) {
+            rolls.extend(std::iter::repeat_n(rules.face(face), times));
+        }
+        let test = FairnessTest::new(&rules, &rolls).unwrap();
+        assert_eq!(test.rolls, 60);
+        assert_eq!(test.degrees_of_freedom, 5);
+        // Expected 10 of each: (100 + 0 + 0 + 0 + 0 + 100) / 10.
+        assert!((test.chi_square - 20.0).abs() < 1e-12);
+        // 2 (20 ln 2 + 4 * 10 ln 1).
+        assert!((test.g - 40.0 * 2f64.ln()).abs() < 1e-12);
+        assert!(test.chi_square_p_value() < 0.01);
+        assert!(!test.too_few_rolls());
+        let probabilities = test.face_probabilities(0.95);
+        assert_eq!(probabilities[0].0, DieRoll::Fruit(0));
+        assert!((probabilities[0].1 - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(probabilities[5].1, 0.0);
+
+        // Two bird faces count together, and are expected twice as often.
+        let rules = Rules {
+            bird_faces: 2,
+            ..Rules::default()
+        };
+        let rolls: Vec<_> = (0..7).map(|face| rules.face(face)).collect();
+        let test = FairnessTest::new(&rules, &rolls).unwrap();
+        assert_eq!(test.counts.len(), 6);
+        assert_eq!(test.counts[5].observed, 2);
+        assert!((test.counts[5].expected - 2.0).abs() < 1e-12);
+        assert!(test.chi_square.abs() < 1e-12);
+        assert!((test.chi_square_p_value() - 1.0).abs() < 1e-12);
+        assert!(test.too_few_rolls());
+
+        assert!(FairnessTest::new(&rules, &[]).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! The game as a Markov chain: every state reachable from the start, with the probability of each
+//! roll taking it to the next, written out as a Graphviz DOT graph.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::rules::Rules;
+use crate::solver::Solver;
+use crate::strategy::BasketStrategy;
+use crate::{DieRoll, Game, Outcome};
+
+/// A state of the chain. Every finished game is one of just two states, however it ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    Playing(Game),
+    Over(Outcome),
+}
+
+/// Every state reachable from a start, and the chances of moving between them on each roll.
+#[derive(Debug, Clone)]
+pub struct StateGraph {
+    /// States in the order they were found, starting with the start, with their win probabilities.
+    pub states: Vec<(State, BigRational)>,
+    /// Transition probabilities between states, by index into `states`; each state's add up to 1.
+    pub transitions: BTreeMap<(usize, usize), BigRational>,
+}
+
+impl StateGraph {
+    /// Enumerates the states reachable from the start `rules` describe, with `strategy` using the
+    /// basket.
+    ///
+    /// With `symmetric`, states that only differ by a permutation of the colors are merged into
+    /// their canonical form, which needs a strategy (and a die) that treats every color alike.
+    pub fn new(rules: &Rules, strategy: &dyn BasketStrategy, symmetric: bool) -> StateGraph {
+        assert!(
+            !symmetric || (strategy.is_symmetric() && rules.colors_alike()),
+            "only strategies and dice that treat colors alike have symmetric state graphs"
+        );
+        let normalize = |state: State| match state {
+            State::Playing(game) if symmetric => State::Playing(game.canonical()),
+            state => state,
+        };
+        let start = normalize(State::Playing(Game::new(rules)));
+        let total_weight = BigRational::from_integer(rules.total_weight().into());
+
+        let mut solver = Solver::new(rules, strategy);
+        let mut indices = HashMap::from([(start, 0)]);
+        let mut graph = StateGraph {
+            states: vec![(start, BigRational::zero())],
+            transitions: BTreeMap::new(),
+        };
+        let mut frontier = vec![start];
+        while let Some(state) = frontier.pop() {
+            let from = indices[&state];
+            graph.states[from].1 = match state {
+                State::Playing(game) => solver.win_probability(game),
+                State::Over(Outcome::Won) => BigRational::one(),
+                State::Over(Outcome::Lost) => BigRational::zero(),
+            };
+
+            let State::Playing(game) = state else {
+                continue;
+            };
+            for (roll, weight) in rules.die() {
+                let chance = BigRational::from_integer(weight.into()) / &total_weight;
+                for (next, probability) in successors(rules, strategy, game, roll) {
+                    let next = normalize(next);
+                    let to = *indices.entry(next).or_insert_with(|| {
+                        graph.states.push((next, BigRational::zero()));
+                        frontier.push(next);
+                        graph.states.len() - 1
+                    });
+                    *graph
+                        .transitions
+                        .entry((from, to))
+                        .or_insert_with(BigRational::zero) += probability * &chance;
+                }
+            }
+        }
+        graph
+    }
+
+    /// Writes the graph in Graphviz's DOT language, naming it `name`.
+    pub fn write_dot(&self, mut output: impl Write, name: &str) -> io::Result<()> {
+        writeln!(output, "digraph \"{name}\" {{")?;
+        writeln!(output, "  node [shape=box, style=filled];")?;
+        for (index, (state, win_probability)) in self.states.iter().enumerate() {
+            // Unwrap: a probability always fits in an f64 (though it may be rounded).
+            let win_rate = win_probability.to_f64().unwrap();
+            let label = match state {
+                State::Playing(game) => {
+                    let orchards: Vec<_> = game.orchards.iter().map(u8::to_string).collect();
+                    format!(
+                        "bird {}\\n{}\\n{:.2}%",
+                        game.bird_position,
+                        orchards.join(" "),
+                        100.0 * win_rate
+                    )
+                }
+                State::Over(Outcome::Won) => "won".to_owned(),
+                State::Over(Outcome::Lost) => "lost".to_owned(),
+            };
+            // Shade from red (sure to lose) to green (sure to win).
+            writeln!(
+                output,
+                "  s{index} [label=\"{label}\", fillcolor=\"{:.3} 0.4 1\"];",
+                win_rate / 3.0
+            )?;
+        }
+        for ((from, to), probability) in &self.transitions {
+            writeln!(output, "  s{from} -> s{to} [label=\"{probability}\"];")?;
+        }
+        writeln!(output, "}}")
+    }
+}
+
+/// Where a single `roll` in `game` can lead, and how likely each is given that roll.
+fn successors(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    game: Game,
+    roll: DieRoll,
+) -> Vec<(State, BigRational)> {
+    let arrive = |game: Game, outcome: Option<Outcome>| match outcome {
+        Some(outcome) => State::Over(outcome),
+        None => State::Playing(game),
+    };
+    let mut next = game;
+    match roll {
+        DieRoll::Fruit(orchard) => {
+            let outcome = next.pick(orchard);
+            vec![(arrive(next, outcome), BigRational::one())]
+        }
+        DieRoll::Bird => {
+            let outcome = next.move_bird();
+            vec![(arrive(next, outcome), BigRational::one())]
+        }
+        DieRoll::Basket => basket_successors(strategy, game, rules.basket_picks)
+            .into_iter()
+            .map(|((game, outcome), probability)| (arrive(game, outcome), probability))
+            .collect(),
+    }
+}
+
+/// Where a basket with `picks_left` fruit to take can lead, averaging over the strategy's choices.
+fn basket_successors(
+    strategy: &dyn BasketStrategy,
+    game: Game,
+    picks_left: u8,
+) -> Vec<((Game, Option<Outcome>), BigRational)> {
+    let choices = strategy.choices(&game, picks_left);
+    let share = BigRational::new(1.into(), choices.len().into());
+    choices
+        .into_iter()
+        .flat_map(|orchard| {
+            let mut next = game;
+            match next.pick(orchard) {
+                None if picks_left > 1 => basket_successors(strategy, next, picks_left - 1)
+                    .into_iter()
+                    .map(|(next, probability)| (next, probability * &share))
+                    .collect(),
+                outcome => vec![((next, outcome), share.clone())],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::{LargestFirst, Random};
+
+    fn small() -> Rules {
+        Rules {
+            apples: 2,
+            track_length: 3,
+            ..Rules::default()
+        }
+    }
+
+    #[test]
+    fn transitions_add_up() {
+        for symmetric in [false, true] {
+            let graph = StateGraph::new(&small(), &Random, symmetric);
+            let mut totals = vec![BigRational::zero(); graph.states.len()];
+            for (&(from, _), probability) in &graph.transitions {
+                totals[from] += probability;
+            }
+            for ((state, _), total) in graph.states.iter().zip(totals) {
+                match state {
+                    State::Playing(_) => assert_eq!(total, BigRational::one()),
+                    State::Over(_) => assert!(total.is_zero()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn symmetry_shrinks_the_graph() {
+        let rules = small();
+        let full = StateGraph::new(&rules, &LargestFirst, false);
+        let collapsed = StateGraph::new(&rules, &LargestFirst, true);
+        // Three bird positions, each with every way of leaving 0-2 apples on 4 trees (less the
+        // empty orchard, which is won), plus won and lost.
+        assert_eq!(full.states.len(), 3 * (81 - 1) + 2);
+        // Sorted, there are 15 - 1 ways of leaving 0-2 apples on 4 trees.
+        assert_eq!(collapsed.states.len(), 3 * (15 - 1) + 2);
+        assert_eq!(collapsed.states[0].1, full.states[0].1);
+
+        let mut dot = Vec::new();
+        collapsed.write_dot(&mut dot, "small").unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph \"small\" {"));
+        assert_eq!(dot.matches("->").count(), collapsed.transitions.len());
+    }
+}
@@ -0,0 +1,213 @@
+//! Win rates for "My Very First Games - First Orchard" (and the classic Orchard it grew out of),
+//! a cooperative game where the players race a bird to pick every fruit off the trees.
+//!
+//! [`Game`] is the state of one game and [`Game::apply`] plays a single roll of the die;
+//! [`rules::Rules`] describes the edition and any house rules. On top of that sit the Monte Carlo
+//! simulator in [`simulate`] (and its [`variance`]-reduced estimators), the exact [`solver`], the
+//! optimal basket [`policy`], and the [`odds`] from any state of a game in progress; [`report`]
+//! writes results out for other programs to read, [`trace`] records single games in full,
+//! [`referee`] runs a real game at the table, and [`die`] fits and tests a real die from a log of
+//! its rolls.
+
+use rand::Rng;
+
+pub mod competition;
+pub mod die;
+pub mod graph;
+pub mod odds;
+pub mod players;
+pub mod policy;
+pub mod progress;
+pub mod referee;
+pub mod report;
+pub mod rules;
+pub mod simulate;
+pub mod solver;
+pub mod stats;
+pub mod strategy;
+pub mod trace;
+pub mod variance;
+
+use rules::Rules;
+use strategy::BasketStrategy;
+
+/// Most fruit colors (and so trees, and die faces) any of the rules we model use.
+pub const MAX_COLORS: usize = 8;
+
+/// Names for each fruit color, in orchard order; First Orchard uses the first four.
+pub const COLOR_NAMES: [&str; MAX_COLORS] = [
+    "red", "green", "blue", "yellow", "purple", "orange", "pink", "white",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DieRoll {
+    /// Pick a fruit from the orchard with this index.
+    Fruit(usize),
+    Basket,
+    Bird,
+}
+
+/// Apples left on each tree, for however many colors the rules call for.
+///
+/// Kept in a fixed-size array (with unused colors always empty) so that `Game` stays `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Orchards {
+    colors: u8,
+    apples: [u8; MAX_COLORS],
+}
+
+impl Orchards {
+    pub fn new(colors: u8, apples: u8) -> Orchards {
+        let mut orchards = Orchards {
+            colors,
+            apples: [0; MAX_COLORS],
+        };
+        orchards.fill(apples);
+        orchards
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for Orchards {
+    fn from(apples: [u8; N]) -> Orchards {
+        let mut orchards = Orchards::new(N as u8, 0);
+        orchards.copy_from_slice(&apples);
+        orchards
+    }
+}
+
+impl TryFrom<&[u8]> for Orchards {
+    type Error = String;
+
+    fn try_from(apples: &[u8]) -> Result<Orchards, String> {
+        if apples.is_empty() || apples.len() > MAX_COLORS {
+            return Err(format!("there must be between 1 and {MAX_COLORS} orchards"));
+        }
+        let mut orchards = Orchards::new(apples.len() as u8, 0);
+        orchards.copy_from_slice(apples);
+        Ok(orchards)
+    }
+}
+
+impl std::ops::Deref for Orchards {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.apples[..usize::from(self.colors)]
+    }
+}
+
+impl std::ops::DerefMut for Orchards {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.apples[..usize::from(self.colors)]
+    }
+}
+
+impl std::fmt::Debug for Orchards {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Game {
+    /// Tracks how close the bird is to the orchard; at 0, the player(s) lose(s).
+    ///
+    /// There are five tiles included in the game box; to allow for 'difficulty' adjustment this
+    /// value generally spans four to six:
+    ///  * Starts on tile 1, lose when arriving at tile 5,
+    ///  * Starts on tile 1, lose when moving 'off' tile 5,
+    ///  * Starts on tile '0', lose when moving 'off' tile 5.
+    pub bird_position: u8,
+
+    /// Number of apples left in each orchard: [red, green, blue, yellow, ...].
+    pub orchards: Orchards,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Outcome {
+    Won,
+    Lost,
+}
+
+impl Game {
+    pub fn new(rules: &Rules) -> Game {
+        Game {
+            bird_position: rules.track_length,
+            orchards: Orchards::new(rules.colors, rules.apples),
+        }
+    }
+
+    pub fn apply(
+        &mut self,
+        rules: &Rules,
+        roll: DieRoll,
+        strategy: &(impl BasketStrategy + ?Sized),
+        rng: &mut impl Rng,
+    ) -> Option<Outcome> {
+        match roll {
+            DieRoll::Fruit(orchard) => self.pick(orchard),
+            DieRoll::Basket => {
+                let mut outcome = None;
+                for picks_left in (1..=rules.basket_picks).rev() {
+                    outcome = self.pick(strategy.choose(self, picks_left, rng));
+                    if outcome.is_some() {
+                        break;
+                    }
+                }
+                outcome
+            }
+            DieRoll::Bird => self.move_bird(),
+        }
+    }
+
+    /// The same state with its orchards sorted, fullest first.
+    ///
+    /// Relabelling the colors of a state relabels the fruit faces of the die the same way, and
+    /// every fruit face is equally likely, so each roll from a state has a matching roll from its
+    /// canonical state that leads to the canonical form of the same successor. Under a strategy
+    /// that treats colors alike ([`BasketStrategy::is_symmetric`]) a state and its canonical form
+    /// therefore have exactly the same chances, and solvers only need to visit canonical states.
+    pub fn canonical(&self) -> Game {
+        let mut game = *self;
+        game.orchards.sort_unstable_by(|a, b| b.cmp(a));
+        game
+    }
+
+    /// Takes an apple (if there is one) from the given orchard.
+    pub fn pick(&mut self, orchard: usize) -> Option<Outcome> {
+        self.orchards[orchard] = self.orchards[orchard].saturating_sub(1);
+        self.outcome()
+    }
+
+    pub fn move_bird(&mut self) -> Option<Outcome> {
+        self.bird_position = self.bird_position.saturating_sub(1);
+        self.outcome()
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        if self.bird_position == 0 {
+            Some(Outcome::Lost)
+        } else if self.orchards.iter().all(|&apples| apples == 0) {
+            Some(Outcome::Won)
+        } else {
+            None
+        }
+    }
+
+    /// Plays a game to the end, returning how it ended and how many rolls that took.
+    pub fn full_game(
+        rules: &Rules,
+        strategy: &(impl BasketStrategy + ?Sized),
+        rng: &mut impl Rng,
+    ) -> (Outcome, u32) {
+        let mut game = Game::new(rules);
+        let mut turns = 0;
+        loop {
+            let roll = rng.sample(rules);
+            turns += 1;
+            if let Some(outcome) = game.apply(rules, roll, strategy, rng) {
+                return (outcome, turns);
+            }
+        }
+    }
+}
@@ -1,123 +1,1597 @@
-use indicatif::ParallelProgressIterator;
-use rand::distributions::{Standard, Uniform};
-use rand::prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use indicatif::ProgressBar;
+use num_traits::ToPrimitive;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rayon::prelude::*;
 
-#[derive(Debug)]
-enum DieRoll {
-    Red = 0,
-    Green = 1,
-    Blue = 2,
-    Yellow = 3,
-    Basket = 4,
-    Bird = 5,
+use orchard::competition::{play_competitive_games, CompetitionSolution, CompetitionSolver};
+use orchard::die;
+use orchard::graph::StateGraph;
+use orchard::odds::Odds;
+use orchard::players::play_team_games;
+use orchard::policy::Policy;
+use orchard::progress::{Silent, WinRateBar};
+use orchard::referee;
+use orchard::report::{self, Record, RecordWriter};
+use orchard::rules::Rules;
+use orchard::simulate::{estimate_win_rate, estimate_win_rate_to, play_games, Tally};
+use orchard::stats::{GameLengths, Interval};
+use orchard::strategy::{self, BasketStrategy};
+use orchard::trace::{read_traces, trace_games, Trace, TraceWriter};
+use orchard::variance::{self, Estimator};
+use orchard::{solver, DieRoll, Game, Orchards, Outcome, COLOR_NAMES};
+
+/// The First Orchard difficulty levels we play at home, named for convenience.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Preset {
+    Easy,
+    Normal,
+    Hard,
 }
 
-impl Distribution<DieRoll> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> DieRoll {
-        match Uniform::new(0, 6).sample(rng) {
-            0 => DieRoll::Red,
-            1 => DieRoll::Green,
-            2 => DieRoll::Blue,
-            3 => DieRoll::Yellow,
-            4 => DieRoll::Basket,
-            5 => DieRoll::Bird,
-            _ => unreachable!(),
+impl Preset {
+    fn bird_position(self) -> u8 {
+        match self {
+            Preset::Easy => 6,
+            Preset::Normal => 5,
+            Preset::Hard => 4,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Game {
-    /// Tracks how close the bird is to the orchard; at 0, the player(s) lose(s).
-    ///
-    /// There are five tiles included in the game box; to allow for 'difficulty' adjustment this
-    /// value generally spans four to six:
-    ///  * Starts on tile 1, lose when arriving at tile 5,
-    ///  * Starts on tile 1, lose when moving 'off' tile 5,
-    ///  * Starts on tile '0', lose when moving 'off' tile 5.
-    bird_position: u8,
+/// How the basket face picks an orchard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Strategy {
+    /// Take from whichever orchard has the most apples left.
+    Largest,
+    /// Take from whichever orchard has the fewest apples left.
+    Smallest,
+    /// Take from any orchard with apples left.
+    Random,
+    /// Empty the orchards one at a time, in the order given by --color-order.
+    Order,
+    /// Always take the --favourite color while it lasts, then any color.
+    Favourite,
+    /// Take whichever apple gives the best chance of winning.
+    Optimal,
+}
+
+/// Ways to estimate the win rate from simulated games; see `variance::Estimator`.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EstimatorKind {
+    /// Count the games won.
+    Plain,
+    /// Pair each game with one whose die rolls are mirrored, birds for baskets.
+    Antithetic,
+    /// Correct the wins by how many birds came up in the first few rolls.
+    Control,
+    /// Stop once --exact-fruit fruit are left, and take the exact win probability from there.
+    Conditional,
+}
+
+/// Fruit colors, in orchard order; see `COLOR_NAMES`.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Color {
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Purple,
+    Orange,
+    Pink,
+    White,
+}
+
+impl Color {
+    fn orchard(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// Human-readable progress and results.
+    Text,
+    /// One CSV row per run, with a header.
+    Csv,
+    /// A JSON array of runs, printed once they're all done.
+    Json,
+    /// One JSON object per run, printed as each finishes.
+    Ndjson,
+}
+
+impl Format {
+    /// The record format, for anything but plain text.
+    fn records(self) -> Option<report::Format> {
+        match self {
+            Format::Text => None,
+            Format::Csv => Some(report::Format::Csv),
+            Format::Json => Some(report::Format::Json),
+            Format::Ndjson => Some(report::Format::Ndjson),
+        }
+    }
+}
 
-    /// Number of apples left in each orchard: [red, green, blue, yellow].
-    orchards: [u8; 4],
+/// Which published game to take the rules from.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Edition {
+    /// My Very First Games - First Orchard.
+    FirstOrchard,
+    /// The classic Orchard (Obstgarten), with a raven in place of the bird.
+    Orchard,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-enum Outcome {
-    Won,
-    Lost,
+impl Edition {
+    fn rules(self) -> Rules {
+        match self {
+            Edition::FirstOrchard => Rules::default(),
+            Edition::Orchard => Rules::orchard(),
+        }
+    }
+}
+
+/// Where the bird starts, either by position or by preset name.
+#[derive(Debug, Args)]
+struct Starts {
+    /// Starting bird positions to evaluate; defaults to every preset for First Orchard, and the
+    /// full raven puzzle for Orchard.
+    bird_positions: Vec<u8>,
+
+    /// Named First Orchard starting positions to evaluate, in addition to any given explicitly.
+    #[arg(short, long, value_enum)]
+    preset: Vec<Preset>,
+}
+
+impl Starts {
+    fn runs(&self, edition: Edition) -> Vec<(Option<Preset>, u8)> {
+        let runs: Vec<_> = self
+            .preset
+            .iter()
+            .map(|&preset| (Some(preset), preset.bird_position()))
+            .chain(self.bird_positions.iter().map(|&pos| (None, pos)))
+            .collect();
+        if !runs.is_empty() {
+            return runs;
+        }
+        match edition {
+            Edition::FirstOrchard => [Preset::Easy, Preset::Normal, Preset::Hard]
+                .into_iter()
+                .map(|preset| (Some(preset), preset.bird_position()))
+                .collect(),
+            Edition::Orchard => vec![(None, edition.rules().track_length)],
+        }
+    }
+
+    /// The furthest-back start, whose reachable states include every other start's.
+    fn hardest(&self, edition: Edition) -> u8 {
+        // Unwrap: `runs` is never empty.
+        self.runs(edition)
+            .iter()
+            .map(|&(_, pos)| pos)
+            .max()
+            .unwrap()
+    }
+}
+
+/// The game's setup, apart from where the bird starts.
+#[derive(Debug, Args)]
+struct RulesArgs {
+    /// Which game's rules to play by; repeat to compare them side by side.
+    #[arg(short, long, value_enum, default_values_t = [Edition::FirstOrchard])]
+    game: Vec<Edition>,
+
+    /// Apples in each orchard at the start of the game, instead of the game's usual number.
+    #[arg(short, long)]
+    apples: Option<u8>,
+
+    /// Number of fruit colors (and orchards), instead of the game's usual number.
+    #[arg(long)]
+    colors: Option<u8>,
+
+    /// Number of bird faces on the die, instead of the game's usual number.
+    #[arg(long)]
+    bird_faces: Option<u8>,
+
+    /// Number of basket faces on the die, instead of the game's usual number.
+    #[arg(long)]
+    basket_faces: Option<u8>,
+
+    /// Fruit taken for each basket rolled, instead of the game's usual number.
+    #[arg(long)]
+    basket_picks: Option<u8>,
+
+    /// Play with a loaded die: how likely each face is relative to the others, fruit colors
+    /// first, then baskets, then birds (e.g. 2,1,1,1,1,1 for a die that favours red).
+    #[arg(long, value_delimiter = ',')]
+    face_weights: Vec<u32>,
+
+    /// Play with a die fitted to a log of real rolls (face names separated by spaces, commas or
+    /// newlines; '#' starts a comment).
+    #[arg(long, value_name = "ROLL_LOG", conflicts_with = "face_weights")]
+    fit_die: Option<PathBuf>,
 }
 
-impl Game {
-    fn new(bird_position: u8) -> Game {
-        Game {
-            bird_position,
-            orchards: [4, 4, 4, 4],
+impl RulesArgs {
+    /// The rules for a game of `edition` with the bird starting at `bird_position`.
+    fn rules(&self, edition: Edition, bird_position: u8) -> Rules {
+        let defaults = edition.rules();
+        let rules = Rules {
+            colors: self.colors.unwrap_or(defaults.colors),
+            apples: self.apples.unwrap_or(defaults.apples),
+            bird_faces: self.bird_faces.unwrap_or(defaults.bird_faces),
+            basket_faces: self.basket_faces.unwrap_or(defaults.basket_faces),
+            basket_picks: self.basket_picks.unwrap_or(defaults.basket_picks),
+            track_length: bird_position,
+            face_weights: self.face_weights.clone(),
+        };
+        let rules = match &self.fit_die {
+            Some(path) => fit_die(rules, path),
+            None => rules,
+        };
+        if let Err(message) = rules.validate() {
+            Cli::command()
+                .error(ErrorKind::InvalidValue, message)
+                .exit();
         }
+        rules
+    }
+}
+
+/// `rules` with their die fitted to the roll log at `path`.
+fn fit_die(rules: Rules, path: &Path) -> Rules {
+    let fitted = File::open(path)
+        .and_then(|file| die::read_rolls(BufReader::new(file), &rules))
+        .map_err(|error| error.to_string())
+        .and_then(|rolls| die::fit_weights(&rules, &rolls));
+    match fitted {
+        Ok(face_weights) => Rules {
+            face_weights,
+            ..rules
+        },
+        Err(message) => Cli::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!("can't fit the die to {}: {message}", path.display()),
+            )
+            .exit(),
     }
+}
+
+/// Estimate (and compute exactly) the odds of beating the bird in First Orchard.
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    simulate: SimulateArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Find the basket choices that maximize the win rate, and compare them with largest-first.
+    Policy(PolicyArgs),
+    /// Simulate several players taking turns, and see whose rolls win and lose the game.
+    Players(PlayersArgs),
+    /// Play the house rule where players keep the fruit they pick, and the one with the most wins.
+    Compete(PlayersArgs),
+    /// Check the games in a trace file against the rules, and optionally step through one.
+    Replay(ReplayArgs),
+    /// Run every combination of ranges of rules and strategies, and print one table of results.
+    Sweep(SweepArgs),
+    /// Referee a real game at the table: roll for the players (or take their rolls), and show the
+    /// odds at every step.
+    Play(PlayArgs),
+    /// Work out the odds from a game in progress, and the best fruit to take from a basket.
+    Odds(OddsArgs),
+    /// Compare variance-reduced estimates of the win rate with plain simulation.
+    Variance(VarianceArgs),
+    /// Write the game's state graph, with every transition's probability, as Graphviz DOT.
+    Graph(GraphArgs),
+    /// Test a log of real die rolls for fairness, and see what the measured die does to the odds.
+    Fairness(FairnessArgs),
+}
+
+/// Which basket strategies to use, and how to set them up.
+#[derive(Debug, Args)]
+struct StrategyArgs {
+    /// How to choose an orchard when the basket is rolled; repeat to compare strategies.
+    #[arg(short, long, value_enum, default_values_t = [Strategy::Largest])]
+    strategy: Vec<Strategy>,
 
-    fn apply(&mut self, roll: DieRoll) -> Option<Outcome> {
-        match roll {
-            DieRoll::Red | DieRoll::Green | DieRoll::Blue | DieRoll::Yellow => {
-                let i = roll as usize;
-                self.orchards[i] = self.orchards[i].saturating_sub(1);
+    #[command(flatten)]
+    setup: StrategySetup,
+}
+
+/// Settings for the strategies that need them.
+#[derive(Debug, Args)]
+struct StrategySetup {
+    /// Color order for the 'order' strategy.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [Color::Red, Color::Green, Color::Blue, Color::Yellow]
+    )]
+    color_order: Vec<Color>,
+
+    /// Favourite color for the 'favourite' strategy.
+    #[arg(long, value_enum, default_value_t = Color::Red)]
+    favourite: Color,
+}
+
+impl StrategySetup {
+    /// Builds `strategy` for games played by `rules`, or any shorter bird track.
+    fn basket_strategy(&self, strategy: Strategy, rules: &Rules) -> Box<dyn BasketStrategy> {
+        match strategy {
+            Strategy::Largest => Box::new(strategy::LargestFirst),
+            Strategy::Smallest => Box::new(strategy::SmallestFirst),
+            Strategy::Random => Box::new(strategy::Random),
+            Strategy::Order => Box::new(strategy::ColorOrder(
+                self.color_order
+                    .iter()
+                    .map(|color| color.orchard())
+                    .collect(),
+            )),
+            Strategy::Favourite => {
+                if self.favourite.orchard() >= usize::from(rules.colors) {
+                    Cli::command()
+                        .error(
+                            ErrorKind::InvalidValue,
+                            format!(
+                                "there is no {} orchard to favour",
+                                value_name(self.favourite)
+                            ),
+                        )
+                        .exit();
+                }
+                Box::new(strategy::Favourite(self.favourite.orchard()))
             }
-            DieRoll::Basket => {
-                // Without further justification, we assume the optimal strategy is to decrement
-                // the largest remaining orchard's count.
-                //
-                // Unwrap: we always have four orchards.
-                let largest_pile = self.orchards.iter_mut().max().unwrap();
-                *largest_pile = largest_pile.saturating_sub(1);
+            Strategy::Optimal => Box::new(Policy::optimal(rules)),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct SimulateArgs {
+    #[command(flatten)]
+    starts: Starts,
+
+    /// Number of games to simulate per starting position (at most, with --precision).
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 1_000_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    games: u64,
+
+    /// Instead of a fixed number of games, simulate until the confidence interval on the win
+    /// rate is within this many percentage points either way, e.g. 0.01.
+    #[arg(long, value_parser = parse_precision)]
+    precision: Option<f64>,
+
+    /// Confidence level for the win rate's confidence interval.
+    #[arg(long, default_value_t = 0.95, value_parser = parse_confidence)]
+    confidence: f64,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    #[command(flatten)]
+    strategies: StrategyArgs,
+
+    /// Seed for the simulation; runs with the same seed (and rules, strategy and game count) give
+    /// the same results, whatever the number of threads. Random if not given.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Print game length percentiles and a histogram, split by outcome.
+    #[arg(long)]
+    lengths: bool,
+
+    /// Also solve exactly, for comparison, whenever the rules have at most this many states.
+    #[arg(long, default_value_t = 1_000_000)]
+    max_exact_states: u64,
+
+    /// Write every roll of the first --trace-games games of each run to this trace file, to be
+    /// checked or stepped through later with `replay`.
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Number of games to trace from each run, with --trace.
+    #[arg(long, default_value_t = 1000)]
+    trace_games: u64,
+
+    /// How to print results.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Debug, Args)]
+struct PolicyArgs {
+    #[command(flatten)]
+    starts: Starts,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    /// Print the best basket choice for every state.
+    #[arg(long)]
+    table: bool,
+}
+
+#[derive(Debug, Args)]
+struct SweepArgs {
+    /// Which game's rules to start from; anything not swept keeps its usual value.
+    #[arg(short, long, value_enum, default_value_t = Edition::FirstOrchard)]
+    game: Edition,
+
+    /// Starting bird positions, e.g. "4-6" or "2,4,6-8"; defaults to the game's usual start.
+    #[arg(long, value_delimiter = ',', value_parser = parse_range)]
+    bird_positions: Vec<RangeInclusive<u8>>,
+
+    /// Apples in each orchard at the start of the game, as ranges like --bird-positions.
+    #[arg(short, long, value_delimiter = ',', value_parser = parse_range)]
+    apples: Vec<RangeInclusive<u8>>,
+
+    /// Numbers of fruit colors (and orchards), as ranges like --bird-positions.
+    #[arg(long, value_delimiter = ',', value_parser = parse_range)]
+    colors: Vec<RangeInclusive<u8>>,
+
+    /// Numbers of bird faces on the die, as ranges like --bird-positions.
+    #[arg(long, value_delimiter = ',', value_parser = parse_range)]
+    bird_faces: Vec<RangeInclusive<u8>>,
+
+    #[command(flatten)]
+    strategies: StrategyArgs,
+
+    /// Solve exactly whenever the rules have at most this many states, and simulate otherwise.
+    #[arg(long, default_value_t = 20_000)]
+    max_exact_states: u64,
+
+    /// Number of games to simulate for each combination too large to solve exactly.
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    games: u64,
+
+    /// Confidence level for simulated win rates' confidence intervals.
+    #[arg(long, default_value_t = 0.95, value_parser = parse_confidence)]
+    confidence: f64,
+
+    /// Seed for the simulations, shared by every combination. Random if not given.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How to print results.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Debug, Args)]
+struct PlayersArgs {
+    /// Each player's basket strategy, in turn order; repeat once per player.
+    #[arg(long = "player", value_enum, required = true)]
+    players: Vec<Strategy>,
+
+    #[command(flatten)]
+    setup: StrategySetup,
+
+    #[command(flatten)]
+    starts: Starts,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    /// Number of games to simulate per starting position.
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 10_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    games: u64,
+
+    /// Confidence level for the win rates' confidence intervals.
+    #[arg(long, default_value_t = 0.95, value_parser = parse_confidence)]
+    confidence: f64,
+
+    /// Seed for the simulation. Random if not given.
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct PlayArgs {
+    #[command(flatten)]
+    starts: Starts,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    /// Seed for the rolls made for the players. Random if not given.
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct OddsArgs {
+    /// Steps the bird has left before it reaches the orchard.
+    #[arg(long)]
+    bird: u8,
+
+    /// Fruit left on each tree, in color order (red, green, blue, yellow, ...), e.g. "2,0,1,0".
+    #[arg(long, value_delimiter = ',', required = true)]
+    orchards: Vec<u8>,
+
+    /// The basket has just been rolled, with this many fruit (by default, all of them) still to
+    /// take from it.
+    #[arg(long, num_args = 0..=1)]
+    basket: Option<Option<u8>>,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+}
+
+#[derive(Debug, Args)]
+struct VarianceArgs {
+    #[command(flatten)]
+    starts: Starts,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    #[command(flatten)]
+    strategies: StrategyArgs,
+
+    /// Estimators to compare; defaults to all of them.
+    #[arg(short, long, value_enum)]
+    estimator: Vec<EstimatorKind>,
+
+    /// Fruit left at which the conditional estimator stops a game and solves the rest exactly.
+    #[arg(long, default_value_t = 4)]
+    exact_fruit: u8,
+
+    /// Number of games to simulate for each estimator (pairs of games, for antithetic).
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    games: u64,
+
+    /// Confidence level for the win rates' confidence intervals.
+    #[arg(long, default_value_t = 0.95, value_parser = parse_confidence)]
+    confidence: f64,
+
+    /// Seed for the simulations, shared by every estimator. Random if not given.
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct GraphArgs {
+    #[command(flatten)]
+    starts: Starts,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    /// How to choose an orchard when the basket is rolled.
+    #[arg(short, long, value_enum, default_value_t = Strategy::Largest)]
+    strategy: Strategy,
+
+    #[command(flatten)]
+    setup: StrategySetup,
+
+    /// Merge states that only differ in which color is which (not for 'order' or 'favourite').
+    #[arg(long)]
+    symmetric: bool,
+
+    /// File to write the graphs to, one per start; printed if not given.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct FairnessArgs {
+    /// Log of real rolls: face names (red, basket, bird, ...) separated by spaces, commas or
+    /// newlines, with '#' starting a comment.
+    log: PathBuf,
+
+    #[command(flatten)]
+    starts: Starts,
+
+    #[command(flatten)]
+    rules: RulesArgs,
+
+    /// How to choose an orchard when the basket is rolled.
+    #[arg(short, long, value_enum, default_value_t = Strategy::Largest)]
+    strategy: Strategy,
+
+    #[command(flatten)]
+    setup: StrategySetup,
+
+    /// Confidence level for the face probabilities' confidence intervals.
+    #[arg(long, default_value_t = 0.95, value_parser = parse_confidence)]
+    confidence: f64,
+}
+
+#[derive(Debug, Args)]
+struct ReplayArgs {
+    /// Trace file, as written by --trace.
+    file: PathBuf,
+
+    /// Print every roll and state of this game, counting from 1 through the whole file.
+    #[arg(long)]
+    show: Option<usize>,
+}
+
+/// Parses a single number ("5") or an inclusive range of them ("3-6").
+fn parse_range(value: &str) -> Result<RangeInclusive<u8>, String> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<u8>()
+            .map_err(|error| format!("'{n}' is not a number from 0 to 255: {error}"))
+    };
+    let range = match value.split_once('-') {
+        Some((low, high)) => parse(low)?..=parse(high)?,
+        None => parse(value)?..=parse(value)?,
+    };
+    if range.is_empty() {
+        return Err(format!("'{value}' is an empty range"));
+    }
+    Ok(range)
+}
+
+/// Parses a precision in percentage points, which has to be more than none at all.
+fn parse_precision(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(precision) if precision > 0.0 && precision.is_finite() => Ok(precision),
+        _ => Err(format!(
+            "'{value}' is not a positive number of percentage points"
+        )),
+    }
+}
+
+/// Parses a confidence level, strictly between 0 and 1.
+fn parse_confidence(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(confidence) if confidence > 0.0 && confidence < 1.0 => Ok(confidence),
+        _ => Err(format!(
+            "'{value}' is not a confidence level between 0 and 1, e.g. 0.95"
+        )),
+    }
+}
+
+/// Every value in `ranges`, or just `default` if there aren't any.
+fn swept(ranges: &[RangeInclusive<u8>], default: u8) -> Vec<u8> {
+    if ranges.is_empty() {
+        return vec![default];
+    }
+    ranges.iter().cloned().flatten().collect()
+}
+
+/// The name clap knows a `ValueEnum` by, for printing it back to the user.
+fn value_name(value: impl ValueEnum) -> String {
+    // Unwrap: none of our values are skipped.
+    value.to_possible_value().unwrap().get_name().to_owned()
+}
+
+fn simulate(args: &SimulateArgs) -> io::Result<()> {
+    // Even unseeded runs get a seed, so that any result can be reproduced after the fact.
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut records = match args.format.records() {
+        Some(format) => Some(RecordWriter::new(io::stdout(), format)?),
+        None => {
+            println!("Seed {seed}");
+            None
+        }
+    };
+    let mut traces = match &args.trace {
+        Some(path) => Some(TraceWriter::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
+
+    for &edition in &args.rules.game {
+        let hardest = args.rules.rules(edition, args.starts.hardest(edition));
+        for &strategy in &args.strategies.strategy {
+            let basket_strategy = args.strategies.setup.basket_strategy(strategy, &hardest);
+            for (preset, bird_position) in args.starts.runs(edition) {
+                let rules = args.rules.rules(edition, bird_position);
+                if records.is_none() {
+                    let game = value_name(edition);
+                    let strategy = value_name(strategy);
+                    match preset {
+                        Some(preset) => println!(
+                            "Estimating {game} win rate for '{}' mode \
+                             (start pos = {bird_position}, strategy = {strategy})...",
+                            value_name(preset)
+                        ),
+                        None => println!(
+                            "Estimating {game} win rate for start pos = {bird_position} \
+                             (strategy = {strategy})..."
+                        ),
+                    }
+                }
+
+                let progress = WinRateBar::new(ProgressBar::new(args.games), args.confidence);
+                let tally = match args.precision {
+                    Some(precision) => estimate_win_rate_to(
+                        &rules,
+                        basket_strategy.as_ref(),
+                        precision / 100.0,
+                        args.confidence,
+                        args.games,
+                        seed,
+                        &progress,
+                    ),
+                    None => estimate_win_rate(
+                        &rules,
+                        basket_strategy.as_ref(),
+                        args.games,
+                        seed,
+                        &progress,
+                    ),
+                };
+                if let Some(traces) = &mut traces {
+                    let note = format!(
+                        "{}, start pos = {bird_position}, strategy = {}, seed = {seed}",
+                        value_name(edition),
+                        value_name(strategy)
+                    );
+                    traces.run(&rules, &note)?;
+                    // Only games the run played, which --precision may have stopped short of -n.
+                    let games = args.trace_games.min(tally.won + tally.lost);
+                    for trace in trace_games(&rules, basket_strategy.as_ref(), games, seed) {
+                        traces.game(&trace)?;
+                    }
+                }
+
+                let Tally { won, lost, .. } = tally;
+                let interval = tally.interval(args.confidence);
+                let lengths = &tally.lengths;
+                // Simulating is how games too big to solve get answered at all.
+                let exact = (rules.state_count() <= args.max_exact_states)
+                    .then(|| solver::Solver::new(&rules, basket_strategy.as_ref()).solve());
+
+                match &mut records {
+                    None => {
+                        println!(
+                            "Won {won}, lost {lost}, win rate {:.4}% ({}% CI {:.4}% to {:.4}%)",
+                            100.0 * tally.win_rate(),
+                            100.0 * args.confidence,
+                            100.0 * interval.low,
+                            100.0 * interval.high
+                        );
+                        println!(
+                            "Game length: mean {} turns (won {}, lost {}), median {}",
+                            or_dash(lengths.mean(None), 2),
+                            or_dash(lengths.mean(Some(Outcome::Won)), 2),
+                            or_dash(lengths.mean(Some(Outcome::Lost)), 2),
+                            or_dash(lengths.median(None), 0)
+                        );
+                        if args.lengths {
+                            print_lengths(lengths);
+                        }
+                        match &exact {
+                            Some(exact) => {
+                                println!(
+                                    "Exact win rate {:.4}% = {}",
+                                    100.0 * exact.win_rate(),
+                                    exact.win_probability
+                                );
+                                println!("Exact expected length {:.4} turns", exact.mean_turns());
+                            }
+                            None => println!(
+                                "Exact: skipped (up to {} states, more than --max-exact-states)",
+                                rules.state_count()
+                            ),
+                        }
+                    }
+                    Some(records) => {
+                        let record = Record::new(
+                            &value_name(edition),
+                            &rules,
+                            &value_name(strategy),
+                            seed,
+                            &tally,
+                            args.confidence,
+                        );
+                        records.write(match &exact {
+                            Some(exact) => record.with_exact(exact),
+                            None => record,
+                        })?
+                    }
+                }
             }
-            DieRoll::Bird => self.bird_position = self.bird_position.saturating_sub(1),
+        }
+    }
+    if let Some(records) = records {
+        records.finish()?;
+    }
+    if let Some(traces) = traces {
+        traces.finish()?;
+    }
+    Ok(())
+}
+
+/// Percentiles and a histogram of game lengths, for all games and split by outcome.
+fn print_lengths(lengths: &GameLengths) {
+    let percentiles = [0.1, 0.25, 0.5, 0.75, 0.9, 0.99];
+    for (label, outcome) in [
+        ("all", None),
+        ("won", Some(Outcome::Won)),
+        ("lost", Some(Outcome::Lost)),
+    ] {
+        let summary: Vec<String> = percentiles
+            .iter()
+            .filter_map(|&p| {
+                let turns = lengths.percentile(outcome, p)?;
+                Some(format!("p{} {turns}", (100.0 * p) as u32))
+            })
+            .collect();
+        println!(
+            "  {label:>4} ({} games): {}",
+            lengths.games(outcome),
+            summary.join(", ")
+        );
+    }
+
+    let won = lengths.histogram(Some(Outcome::Won));
+    let lost = lengths.histogram(Some(Outcome::Lost));
+    let widest = won.iter().chain(&lost).copied().max().unwrap_or_default();
+    let bar = |count: u64| "#".repeat((40 * count).div_ceil(widest.max(1)) as usize);
+    println!("  turns {:>12} {:>12}", "won", "lost");
+    for turns in 0..won.len().max(lost.len()) {
+        let won = won.get(turns).copied().unwrap_or_default();
+        let lost = lost.get(turns).copied().unwrap_or_default();
+        if won + lost > 0 {
+            println!(
+                "  {turns:>5} {won:>12} {lost:>12} {:<40} {}",
+                bar(won),
+                bar(lost)
+            );
+        }
+    }
+}
+
+fn color_name(orchard: usize) -> &'static str {
+    COLOR_NAMES[orchard]
+}
+
+fn optimize(args: &PolicyArgs) {
+    for &edition in &args.rules.game {
+        println!("Optimal basket policy for {}:", value_name(edition));
+        let policy = Policy::optimal(&args.rules.rules(edition, args.starts.hardest(edition)));
+
+        for (_, bird_position) in args.starts.runs(edition) {
+            let rules = args.rules.rules(edition, bird_position);
+            // Unwrap: the policy covers every start up to the hardest.
+            let optimal = policy
+                .win_probability(&Game::new(&rules))
+                .unwrap()
+                .to_f64()
+                .unwrap();
+            let largest = solver::Solver::new(&rules, &strategy::LargestFirst)
+                .solve()
+                .win_rate();
+            println!(
+                "Start pos = {bird_position}: optimal win rate {:.4}%, \
+                 largest-first {:.4}% ({:+.6} points)",
+                100.0 * optimal,
+                100.0 * largest,
+                100.0 * (largest - optimal)
+            );
         }
 
-        if self.bird_position == 0 {
-            Some(Outcome::Lost)
-        } else if self.orchards.iter().sum::<u8>() == 0 {
-            Some(Outcome::Won)
+        let mistakes = policy.mistakes(&strategy::LargestFirst);
+        if mistakes.is_empty() {
+            println!("Largest-first is optimal in every state.");
         } else {
-            None
+            println!(
+                "Largest-first is suboptimal in {} states; the worst are:",
+                mistakes.len()
+            );
+            for mistake in mistakes.iter().take(10) {
+                println!(
+                    "  bird {}, orchards {:?}, {} picks left: take {} not {}, costing {:.6} points",
+                    mistake.game.bird_position,
+                    mistake.game.orchards,
+                    mistake.picks_left,
+                    color_name(mistake.best),
+                    color_name(mistake.chosen[0]),
+                    100.0 * mistake.cost.to_f64().unwrap()
+                );
+            }
+        }
+
+        if args.table {
+            for (game, picks_left, choice) in policy.table() {
+                println!(
+                    "bird {}, orchards {:?}, {picks_left} picks left: take {}",
+                    game.bird_position,
+                    game.orchards,
+                    color_name(choice)
+                );
+            }
         }
     }
+}
+
+fn play_players(args: &PlayersArgs) {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("Seed {seed}");
+    for &edition in &args.rules.game {
+        let hardest = args.rules.rules(edition, args.starts.hardest(edition));
+        let strategies: Vec<_> = args
+            .players
+            .iter()
+            .map(|&strategy| args.setup.basket_strategy(strategy, &hardest))
+            .collect();
+        let players: Vec<_> = strategies
+            .iter()
+            .map(|strategy| strategy.as_ref())
+            .collect();
 
-    fn full_game(bird_position: u8, rng: &mut impl Rng) -> Outcome {
-        let mut game = Game::new(bird_position);
-        loop {
-            let roll: DieRoll = rng.gen();
-            if let Some(outcome) = game.apply(roll) {
-                return outcome;
+        for (_, bird_position) in args.starts.runs(edition) {
+            let rules = args.rules.rules(edition, bird_position);
+            println!(
+                "Playing {} with {} players from start pos = {bird_position}...",
+                value_name(edition),
+                players.len()
+            );
+            let progress = ProgressBar::new(args.games);
+            let tally = play_team_games(&rules, &players, 0..args.games, seed, &progress);
+            progress.finish();
+
+            let team = &tally.team;
+            let interval = team.interval(args.confidence);
+            println!(
+                "Team won {}, lost {}, win rate {:.4}% ({}% CI {:.4}% to {:.4}%)",
+                team.won,
+                team.lost,
+                100.0 * team.win_rate(),
+                100.0 * args.confidence,
+                100.0 * interval.low,
+                100.0 * interval.high
+            );
+            let turns: u64 = tally.players.iter().map(|player| player.turns).sum();
+            for (seat, (player, &strategy)) in tally.players.iter().zip(&args.players).enumerate() {
+                println!(
+                    "  Player {} ({}): {} of rolls, {} of winning picks, {} of final bird moves",
+                    seat + 1,
+                    value_name(strategy),
+                    share(player.turns, turns),
+                    share(player.winning_picks, team.won),
+                    share(player.final_bird_moves, team.lost)
+                );
             }
         }
     }
 }
 
-fn estimate_win_rate(bird_position: u8) {
-    let n = 1_000_000_000;
-    let (won, lost) = (0..n)
-        .into_par_iter()
-        .progress_count(n)
-        .map(|_| {
-            let mut rng = rand::thread_rng();
-            match Game::full_game(bird_position, &mut rng) {
-                Outcome::Won => (1, 0),
-                Outcome::Lost => (0, 1),
+/// `part` as a percentage of `whole`, or "-" if there's nothing to take a share of (say, no
+/// games were lost, so nobody made a final bird move).
+fn share(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "-".to_owned();
+    }
+    format!("{:.2}%", 100.0 * part as f64 / whole as f64)
+}
+
+/// `value` to `precision` decimal places, or "-" if there isn't one (say, the mean length of
+/// lost games when none were lost).
+fn or_dash(value: Option<impl Into<f64>>, precision: usize) -> String {
+    match value {
+        Some(value) => format!("{:.precision$}", value.into()),
+        None => "-".to_owned(),
+    }
+}
+
+fn compete(args: &PlayersArgs) {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("Seed {seed}");
+    for &edition in &args.rules.game {
+        let hardest = args.rules.rules(edition, args.starts.hardest(edition));
+        let strategies: Vec<_> = args
+            .players
+            .iter()
+            .map(|&strategy| args.setup.basket_strategy(strategy, &hardest))
+            .collect();
+        let players: Vec<_> = strategies
+            .iter()
+            .map(|strategy| strategy.as_ref())
+            .collect();
+
+        for (_, bird_position) in args.starts.runs(edition) {
+            let rules = args.rules.rules(edition, bird_position);
+            println!(
+                "Competing at {} with {} players from start pos = {bird_position}...",
+                value_name(edition),
+                players.len()
+            );
+            let progress = ProgressBar::new(args.games);
+            let tally = play_competitive_games(&rules, &players, 0..args.games, seed, &progress);
+            progress.finish();
+            // Fractions get unwieldy with more than a couple of players; floating point is plenty
+            // to compare with the simulation.
+            let exact: CompetitionSolution<f64> = CompetitionSolver::new(&rules, &players).solve();
+
+            let games = tally.games();
+            let seats = (1..)
+                .zip(&args.players)
+                .zip(tally.wins.iter().zip(&exact.wins));
+            let rows = seats
+                .map(|((seat, &strategy), (&wins, exact))| {
+                    let label = format!("Player {seat} ({})", value_name(strategy));
+                    (label, wins, *exact)
+                })
+                .chain([
+                    ("Tie".to_owned(), tally.ties, exact.tie),
+                    ("Bird".to_owned(), tally.lost, exact.lost()),
+                ]);
+            for (label, count, exact) in rows {
+                let interval = Interval::wilson(count, games, args.confidence);
+                println!(
+                    "  {label}: {:.4}% ({}% CI {:.4}% to {:.4}%), exactly {:.4}%",
+                    100.0 * count as f64 / games as f64,
+                    100.0 * args.confidence,
+                    100.0 * interval.low,
+                    100.0 * interval.high,
+                    100.0 * exact
+                );
             }
+        }
+    }
+}
+
+/// Referees a real game from each start in turn, until one is quit.
+fn play(args: &PlayArgs) -> io::Result<()> {
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    // One reader for every game, so that nothing it has read ahead is lost between them. (Not a
+    // `StdinLock`, which can't be sent to the threads the referee's basket strategy may run on.)
+    let mut input = BufReader::new(io::stdin());
+    for &edition in &args.rules.game {
+        println!("Working out the odds for {}...", value_name(edition));
+        let policy = Policy::optimal(&args.rules.rules(edition, args.starts.hardest(edition)));
+        for (_, bird_position) in args.starts.runs(edition) {
+            let rules = args.rules.rules(edition, bird_position);
+            if referee::play(&rules, &policy, &mut input, io::stdout(), &mut rng)?.is_none() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn odds(args: &OddsArgs) {
+    let orchards = Orchards::try_from(&args.orchards[..]).unwrap_or_else(|message| {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, message)
+            .exit()
+    });
+    let game = Game {
+        bird_position: args.bird,
+        orchards,
+    };
+    for &edition in &args.rules.game {
+        let rules = args.rules.rules(edition, args.bird.max(1));
+        let picks_left = args
+            .basket
+            .map(|picks_left| picks_left.unwrap_or(rules.basket_picks));
+        let odds = Odds::from_state(&rules, game, picks_left).unwrap_or_else(|message| {
+            Cli::command()
+                .error(ErrorKind::InvalidValue, message)
+                .exit()
+        });
+        let trees: Vec<_> = (0..)
+            .zip(game.orchards.iter())
+            .map(|(orchard, apples)| format!("{} {apples}", color_name(orchard)))
+            .collect();
+        println!(
+            "{}, bird {} {} from the orchard, {}:",
+            value_name(edition),
+            args.bird,
+            if args.bird == 1 { "step" } else { "steps" },
+            trees.join(", ")
+        );
+        println!(
+            "  win probability {:.4}% ({}), with the best basket choices",
+            100.0 * odds.win_rate(),
+            odds.win_probability
+        );
+        println!("  expected turns left {:.4}", odds.mean_turns());
+        if let Some(basket) = &odds.basket {
+            let values: Vec<_> = basket
+                .values
+                .iter()
+                .map(|(orchard, value)| {
+                    format!(
+                        "{} {:.4}%",
+                        color_name(*orchard),
+                        100.0 * value.to_f64().unwrap()
+                    )
+                })
+                .collect();
+            println!(
+                "  basket, {} to take: take {} ({})",
+                basket.picks_left,
+                color_name(basket.best),
+                values.join(", ")
+            );
+        }
+    }
+}
+
+fn compare_estimators(args: &VarianceArgs) {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("Seed {seed}");
+    let kinds = if args.estimator.is_empty() {
+        EstimatorKind::value_variants().to_vec()
+    } else {
+        args.estimator.clone()
+    };
+    for &edition in &args.rules.game {
+        let hardest = args.rules.rules(edition, args.starts.hardest(edition));
+        for &strategy in &args.strategies.strategy {
+            let basket_strategy = args.strategies.setup.basket_strategy(strategy, &hardest);
+            for (_, bird_position) in args.starts.runs(edition) {
+                let rules = args.rules.rules(edition, bird_position);
+                println!(
+                    "Estimating {} win rate for start pos = {bird_position} (strategy = {})...",
+                    value_name(edition),
+                    value_name(strategy)
+                );
+                for &kind in &kinds {
+                    let estimator = match kind {
+                        EstimatorKind::Plain => Estimator::Plain,
+                        EstimatorKind::Antithetic => Estimator::Antithetic,
+                        EstimatorKind::Control => Estimator::ControlVariate,
+                        EstimatorKind::Conditional => Estimator::Conditional {
+                            exact_fruit: args.exact_fruit,
+                        },
+                    };
+                    let started = Instant::now();
+                    let progress = ProgressBar::new(args.games);
+                    let estimate = variance::estimate(
+                        &rules,
+                        basket_strategy.as_ref(),
+                        estimator,
+                        0..args.games,
+                        seed,
+                        &progress,
+                    );
+                    progress.finish_and_clear();
+                    let seconds = started.elapsed().as_secs_f64();
+                    let interval = estimate.interval(args.confidence);
+                    // Precision per second is what matters when estimators differ in cost.
+                    let speed = match estimate.variance_reduction() {
+                        Some(reduction) => format!(
+                            "{reduction:.2}x less than plain, {:.0} plain-equivalent games/s",
+                            reduction * estimate.games as f64 / seconds
+                        ),
+                        None => "exact".to_owned(),
+                    };
+                    println!(
+                        "  {:<12} win rate {:.4}% ({}% CI {:.4}% to {:.4}%), \
+                         variance per game {:.5}, {speed}",
+                        value_name(kind),
+                        100.0 * estimate.win_rate,
+                        100.0 * args.confidence,
+                        100.0 * interval.low,
+                        100.0 * interval.high,
+                        estimate.variance
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn graph(args: &GraphArgs) -> io::Result<()> {
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    for &edition in &args.rules.game {
+        let hardest = args.rules.rules(edition, args.starts.hardest(edition));
+        let basket_strategy = args.setup.basket_strategy(args.strategy, &hardest);
+        if args.symmetric && !hardest.colors_alike() {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--symmetric needs a die whose fruit faces are all equally likely",
+                )
+                .exit();
+        }
+        if args.symmetric && !basket_strategy.is_symmetric() {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "--symmetric needs a strategy that treats every color alike, not '{}'",
+                        value_name(args.strategy)
+                    ),
+                )
+                .exit();
+        }
+        for (_, bird_position) in args.starts.runs(edition) {
+            let rules = args.rules.rules(edition, bird_position);
+            let graph = StateGraph::new(&rules, basket_strategy.as_ref(), args.symmetric);
+            let name = format!(
+                "{} from start pos = {bird_position} (strategy = {})",
+                value_name(edition),
+                value_name(args.strategy)
+            );
+            graph.write_dot(&mut output, &name)?;
+            if args.output.is_some() {
+                println!(
+                    "{name}: {} states, {} transitions",
+                    graph.states.len(),
+                    graph.transitions.len()
+                );
+            }
+        }
+    }
+    output.flush()
+}
+
+fn test_fairness(args: &FairnessArgs) -> io::Result<bool> {
+    for &edition in &args.rules.game {
+        let hardest = args.rules.rules(edition, args.starts.hardest(edition));
+        let rolls = match File::open(&args.log)
+            .and_then(|file| die::read_rolls(BufReader::new(file), &hardest))
+        {
+            Ok(rolls) => rolls,
+            Err(error) => {
+                eprintln!("{}: {error}", args.log.display());
+                return Ok(false);
+            }
+        };
+        let test = match die::FairnessTest::new(&hardest, &rolls) {
+            Ok(test) => test,
+            Err(message) => {
+                eprintln!("{}: {message}", args.log.display());
+                return Ok(false);
+            }
+        };
+        let expected_die = if hardest.face_weights.is_empty() {
+            "a fair die"
+        } else {
+            "the die given"
+        };
+        println!(
+            "{} rolls of the {} die, against {expected_die}:",
+            test.rolls,
+            value_name(edition)
+        );
+        for (count, (_, probability, interval)) in test
+            .counts
+            .iter()
+            .zip(test.face_probabilities(args.confidence))
+        {
+            let name = match count.roll {
+                DieRoll::Fruit(orchard) => color_name(orchard),
+                DieRoll::Basket => "basket",
+                DieRoll::Bird => "bird",
+            };
+            let faces = match count.faces {
+                1 => String::new(),
+                faces => format!(" ({faces} faces)"),
+            };
+            println!(
+                "  {:<20} {:>6} rolls, expected {:>8.1}; {:.2}% per face ({}% CI {:.2}% to {:.2}%)",
+                format!("{name}{faces}"),
+                count.observed,
+                count.expected,
+                100.0 * probability,
+                100.0 * args.confidence,
+                100.0 * interval.low,
+                100.0 * interval.high
+            );
+        }
+        println!(
+            "Chi-square test: {:.3} on {} degrees of freedom, p = {:.4}",
+            test.chi_square,
+            test.degrees_of_freedom,
+            test.chi_square_p_value()
+        );
+        println!(
+            "G test:          {:.3} on {} degrees of freedom, p = {:.4}",
+            test.g,
+            test.degrees_of_freedom,
+            test.g_p_value()
+        );
+        if test.too_few_rolls() {
+            println!(
+                "  (some rolls were expected fewer than 5 times, \
+                 so take the p-values with a pinch of salt)"
+            );
+        }
+
+        let face_weights = match die::fit_weights(&hardest, &rolls) {
+            Ok(face_weights) => face_weights,
+            Err(message) => {
+                eprintln!("{}: {message}", args.log.display());
+                return Ok(false);
+            }
+        };
+        let basket_strategy = args.setup.basket_strategy(args.strategy, &hardest);
+        println!(
+            "Exact win rates with the die as measured (strategy = {}):",
+            value_name(args.strategy)
+        );
+        for (_, bird_position) in args.starts.runs(edition) {
+            let rules = args.rules.rules(edition, bird_position);
+            let measured = Rules {
+                face_weights: face_weights.clone(),
+                ..rules.clone()
+            };
+            let expected = solver::Solver::new(&rules, basket_strategy.as_ref())
+                .solve()
+                .win_rate();
+            let actual = solver::Solver::new(&measured, basket_strategy.as_ref())
+                .solve()
+                .win_rate();
+            println!(
+                "  Start pos = {bird_position}: {:.4}% as measured, \
+                 {:.4}% with {expected_die} ({:+.4} points)",
+                100.0 * actual,
+                100.0 * expected,
+                100.0 * (actual - expected)
+            );
+        }
+    }
+    Ok(true)
+}
+
+/// Replays every game in a trace file, returning whether they all checked out.
+fn replay(args: &ReplayArgs) -> io::Result<bool> {
+    let runs = match File::open(&args.file).and_then(|file| read_traces(BufReader::new(file))) {
+        Ok(runs) => runs,
+        Err(error) => {
+            eprintln!("{}: {error}", args.file.display());
+            return Ok(false);
+        }
+    };
+    let mut games = 0;
+    let mut failures = 0;
+    for run in &runs {
+        for (line, recorded) in &run.games {
+            games += 1;
+            match recorded.replay(&run.rules) {
+                Ok(trace) if args.show == Some(games) => {
+                    for note in &run.notes {
+                        println!("# {note}");
+                    }
+                    print_trace(&trace);
+                }
+                Ok(_) => {}
+                Err(message) => {
+                    failures += 1;
+                    println!("Line {line}: {message}");
+                }
+            }
+        }
+    }
+    println!(
+        "Replayed {games} games from {} runs: {} consistent, {failures} not",
+        runs.len(),
+        games - failures
+    );
+    Ok(failures == 0)
+}
+
+fn print_trace(trace: &Trace) {
+    let describe = |game: &Game| {
+        let orchards: Vec<String> = (0..game.orchards.len())
+            .map(|orchard| format!("{} {}", game.orchards[orchard], color_name(orchard)))
+            .collect();
+        format!("bird {}, {}", game.bird_position, orchards.join(", "))
+    };
+    println!("Start: {}", describe(&trace.states[0]));
+    for (turn, (roll, state)) in (1..).zip(trace.rolls.iter().zip(&trace.states[1..])) {
+        let rolled = match roll.roll {
+            DieRoll::Fruit(orchard) => color_name(orchard).to_owned(),
+            DieRoll::Bird => "bird".to_owned(),
+            DieRoll::Basket => {
+                let picks: Vec<_> = roll
+                    .picks
+                    .iter()
+                    .map(|&orchard| color_name(orchard))
+                    .collect();
+                format!("basket, took {}", picks.join(" and "))
+            }
+        };
+        println!("{turn:>4}. {rolled:<24} {}", describe(state));
+    }
+    println!("{:?} after {} turns", trace.outcome, trace.turns());
+}
+
+fn sweep(args: &SweepArgs) -> io::Result<()> {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let defaults = args.game.rules();
+    let mut runs = Vec::new();
+    for track_length in swept(&args.bird_positions, defaults.track_length) {
+        for apples in swept(&args.apples, defaults.apples) {
+            for colors in swept(&args.colors, defaults.colors) {
+                for bird_faces in swept(&args.bird_faces, defaults.bird_faces) {
+                    let rules = Rules {
+                        colors,
+                        apples,
+                        bird_faces,
+                        track_length,
+                        ..defaults.clone()
+                    };
+                    if let Err(message) = rules.validate() {
+                        Cli::command()
+                            .error(ErrorKind::InvalidValue, format!("{message} ({rules:?})"))
+                            .exit();
+                    }
+                    for &strategy in &args.strategies.strategy {
+                        if strategy == Strategy::Optimal
+                            && rules.state_count() > args.max_exact_states
+                        {
+                            Cli::command()
+                                .error(
+                                    ErrorKind::InvalidValue,
+                                    format!(
+                                        "the optimal strategy needs every state solved, and \
+                                         {rules:?} has too many; raise --max-exact-states"
+                                    ),
+                                )
+                                .exit();
+                        }
+                        // Built here, on the main thread, since a bad setting for the strategy
+                        // exits the program; and once, since the optimal one takes a solve.
+                        let basket_strategy =
+                            args.strategies.setup.basket_strategy(strategy, &rules);
+                        runs.push((rules.clone(), strategy, basket_strategy));
+                    }
+                }
+            }
+        }
+    }
+
+    // Each combination is solved or simulated on its own thread; simulations spread further
+    // across the pool as threads free up.
+    let game = value_name(args.game);
+    let progress = ProgressBar::new(runs.len() as u64);
+    let results: Vec<Record> = runs
+        .par_iter()
+        .map(|(rules, strategy, basket_strategy)| {
+            let strategy = value_name(*strategy);
+            let record = if rules.state_count() <= args.max_exact_states {
+                let exact = solver::Solver::new(rules, basket_strategy.as_ref()).solve();
+                Record::exact(&game, rules, &strategy, &exact)
+            } else {
+                let games = 0..args.games;
+                let tally = play_games(rules, basket_strategy.as_ref(), games, seed, &Silent);
+                Record::new(&game, rules, &strategy, seed, &tally, args.confidence)
+            };
+            progress.inc(1);
+            record
         })
-        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+        .collect();
+    progress.finish_and_clear();
+
+    if let Some(format) = args.format.records() {
+        let mut records = RecordWriter::new(io::stdout(), format)?;
+        for record in results {
+            records.write(record)?;
+        }
+        records.finish()?;
+        return Ok(());
+    }
 
-    let win_rate_percent = 100.0 * won as f64 / (won + lost) as f64;
-    println!("Won {won}, lost {lost}, win rate {win_rate_percent:.2}%");
+    if results.iter().any(|record| record.seed.is_some()) {
+        println!("Seed {seed}");
+    }
+    println!(
+        "{:>4} {:>6} {:>6} {:>10} {:<10} {:>9} {:>21} {:>10}",
+        "bird", "colors", "apples", "bird faces", "strategy", "win rate", "interval", "mean turns"
+    );
+    for record in results {
+        let rules = &record.rules;
+        let interval = match record.seed {
+            Some(_) => format!(
+                "{:.4}% to {:.4}%",
+                100.0 * record.ci_low,
+                100.0 * record.ci_high
+            ),
+            None => "exact".to_owned(),
+        };
+        println!(
+            "{:>4} {:>6} {:>6} {:>10} {:<10} {:>8.4}% {:>21} {:>10}",
+            rules.track_length,
+            rules.colors,
+            rules.apples,
+            rules.bird_faces,
+            record.strategy,
+            100.0 * record.win_rate,
+            interval,
+            or_dash(record.mean_turns, 4)
+        );
+    }
+    Ok(())
 }
 
-fn main() {
-    println!("Estimating win rate for 'easy' mode (start pos = 6)...");
-    estimate_win_rate(6);
+fn main() -> io::Result<ExitCode> {
+    let cli = Cli::parse();
+    match &cli.command {
+        None => simulate(&cli.simulate)?,
+        Some(Command::Policy(args)) => optimize(args),
+        Some(Command::Players(args)) => play_players(args),
+        Some(Command::Compete(args)) => compete(args),
+        Some(Command::Replay(args)) => {
+            if !replay(args)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Some(Command::Sweep(args)) => sweep(args)?,
+        Some(Command::Play(args)) => play(args)?,
+        Some(Command::Odds(args)) => odds(args),
+        Some(Command::Variance(args)) => compare_estimators(args),
+        Some(Command::Graph(args)) => graph(args)?,
+        Some(Command::Fairness(args)) => {
+            if !test_fairness(args)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    println!("Estimating win rate for 'normal' mode (start pos = 5)...");
-    estimate_win_rate(5);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_ranges() {
+        assert_eq!(parse_range("5"), Ok(5..=5));
+        assert_eq!(parse_range("3-6"), Ok(3..=6));
+        assert!(parse_range("6-3").is_err());
+        assert!(parse_range("x").is_err());
+        assert_eq!(swept(&[], 4), [4]);
+        assert_eq!(swept(&[2..=3, 6..=6], 4), [2, 3, 6]);
+    }
 
-    println!("Estimating win rate for 'hard' mode (start pos = 4)...");
-    estimate_win_rate(4);
+    #[test]
+    fn precision_and_confidence() {
+        assert_eq!(parse_precision("0.05"), Ok(0.05));
+        assert!(parse_precision("0").is_err());
+        assert!(parse_precision("-1").is_err());
+        assert_eq!(parse_confidence("0.99"), Ok(0.99));
+        assert!(parse_confidence("1").is_err());
+        assert!(parse_confidence("1.5").is_err());
+        assert!(parse_confidence("0").is_err());
+    }
+
+    #[test]
+    fn at_least_one_game() {
+        assert!(Cli::try_parse_from(["orchard", "-n", "0"]).is_err());
+        assert!(
+            Cli::try_parse_from(["orchard", "players", "--player", "largest", "-n", "0"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["orchard", "-n", "1"]).is_ok());
+    }
 }
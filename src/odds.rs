@@ -0,0 +1,157 @@
+//! The odds from any state of a game in progress, with the basket played as well as possible.
+
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::policy::Policy;
+use crate::rules::Rules;
+use crate::solver::Solver;
+use crate::Game;
+
+/// How a game in progress stands, assuming the best basket choices from here on.
+#[derive(Debug, Clone)]
+pub struct Odds {
+    pub win_probability: BigRational,
+    /// Expected number of rolls until the game is over, won or lost, not counting a basket that
+    /// has already been rolled.
+    pub expected_turns: BigRational,
+    /// The choice waiting to be made, if the basket has just been rolled.
+    pub basket: Option<BasketChoice>,
+}
+
+/// The best fruit to take from a basket that has just been rolled, and what every choice is worth.
+#[derive(Debug, Clone)]
+pub struct BasketChoice {
+    /// Fruit still to take from the basket, including this one.
+    pub picks_left: u8,
+    pub best: usize,
+    /// Win probability after taking a fruit from each orchard that has one, in orchard order.
+    pub values: Vec<(usize, BigRational)>,
+}
+
+impl Odds {
+    /// Works out the odds from `game`, a game played by `rules` that isn't over yet.
+    ///
+    /// With `picks_left`, the basket has just been rolled with that many fruit still to take from
+    /// it, and the odds are those of taking the best ones.
+    pub fn from_state(rules: &Rules, game: Game, picks_left: Option<u8>) -> Result<Odds, String> {
+        rules.validate()?;
+        if game.orchards.len() != usize::from(rules.colors) {
+            return Err(format!(
+                "the rules have {} orchards, not {}",
+                rules.colors,
+                game.orchards.len()
+            ));
+        }
+        if game.outcome().is_some() {
+            return Err("that game is already over".to_owned());
+        }
+        if let Some(picks_left) = picks_left {
+            if rules.basket_faces == 0 {
+                return Err("the die has no basket".to_owned());
+            }
+            if picks_left == 0 || picks_left > rules.basket_picks {
+                return Err(format!(
+                    "the basket takes between 1 and {} fruit",
+                    rules.basket_picks
+                ));
+            }
+        }
+
+        let policy = Policy::optimal_from(rules, game);
+        let mut solver = Solver::new(rules, &policy);
+        let basket = picks_left.map(|picks_left| BasketChoice {
+            picks_left,
+            // Unwrap: the policy covers every state reachable from `game`, including `game`.
+            best: policy.choice(&game, picks_left).unwrap(),
+            values: policy.choice_values(&game, picks_left),
+        });
+        let mut next = game;
+        for picks_left in (1..=picks_left.unwrap_or(0)).rev() {
+            // Unwrap: as above.
+            if next
+                .pick(policy.choice(&next, picks_left).unwrap())
+                .is_some()
+            {
+                return Ok(Odds {
+                    win_probability: BigRational::one(),
+                    expected_turns: BigRational::zero(),
+                    basket,
+                });
+            }
+        }
+        Ok(Odds {
+            win_probability: solver.win_probability(next),
+            expected_turns: solver.expected_turns(next),
+            basket,
+        })
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        // Unwrap: a probability always fits in an f64 (though it may be rounded).
+        self.win_probability.to_f64().unwrap()
+    }
+
+    pub fn mean_turns(&self) -> f64 {
+        // Unwrap: as above.
+        self.expected_turns.to_f64().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+
+    fn ratio(numerator: i32, denominator: i32) -> BigRational {
+        BigRational::new(BigInt::from(numerator), BigInt::from(denominator))
+    }
+
+    #[test]
+    fn one_step_from_losing() {
+        // Red, blue or basket leave one fruit, won 2/3 of the time; the bird loses, and anything
+        // else re-rolls.
+        let game = Game {
+            bird_position: 1,
+            orchards: [1, 0, 1, 0].into(),
+        };
+        let odds = Odds::from_state(&Rules::default(), game, None).unwrap();
+        assert_eq!(odds.win_probability, ratio(1, 2));
+        assert!(odds.basket.is_none());
+    }
+
+    #[test]
+    fn basket_waiting_to_be_used() {
+        // Taking red leaves two blue, won with blue or basket (then 2/3) against the bird: 4/9.
+        // Taking blue leaves one of each, 1/2 as above.
+        let game = Game {
+            bird_position: 1,
+            orchards: [1, 0, 2, 0].into(),
+        };
+        let odds = Odds::from_state(&Rules::default(), game, Some(1)).unwrap();
+        let basket = odds.basket.unwrap();
+        assert_eq!(basket.values, [(0, ratio(4, 9)), (2, ratio(1, 2))]);
+        assert_eq!(basket.best, 2);
+        assert_eq!(odds.win_probability, ratio(1, 2));
+
+        // The last fruit wins outright.
+        let game = Game {
+            bird_position: 1,
+            orchards: [0, 0, 1, 0].into(),
+        };
+        let odds = Odds::from_state(&Rules::default(), game, Some(1)).unwrap();
+        assert_eq!(odds.win_probability, BigRational::one());
+        assert_eq!(odds.expected_turns, BigRational::zero());
+    }
+
+    #[test]
+    fn impossible_questions() {
+        let rules = Rules::default();
+        let mut game = Game::new(&rules);
+        assert!(Odds::from_state(&rules, game, Some(2)).is_err());
+        game.orchards = [1, 1].into();
+        assert!(Odds::from_state(&rules, game, None).is_err());
+        game.orchards = [0; 4].into();
+        assert!(Odds::from_state(&rules, game, None).is_err());
+    }
+}
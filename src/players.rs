@@ -0,0 +1,167 @@
+//! Several players taking turns at the die, each with their own idea of how to use the basket.
+//!
+//! The team still wins or loses together, but every game ends on somebody's roll: either the
+//! pick that takes the last fruit, or the bird's final step into the orchard.
+
+use std::ops::Range;
+
+use rand::Rng;
+
+use crate::progress::Progress;
+use crate::rules::Rules;
+use crate::simulate::{play_chunks_from, Tally};
+use crate::strategy::BasketStrategy;
+use crate::{Game, Outcome};
+
+/// How one game with several players ended, and on whose roll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ending {
+    pub outcome: Outcome,
+    pub turns: u32,
+    /// The seat (counting from 0, the first to roll) whose roll ended the game.
+    pub player: usize,
+}
+
+impl Game {
+    /// Plays a game to the end, with the players taking turns in seat order and each using their
+    /// own strategy when they roll the basket.
+    pub fn full_game_with_players(
+        rules: &Rules,
+        players: &[&dyn BasketStrategy],
+        rng: &mut impl Rng,
+    ) -> Ending {
+        assert!(!players.is_empty(), "a game needs at least one player");
+        let mut game = Game::new(rules);
+        let mut turns = 0;
+        loop {
+            let player = turns as usize % players.len();
+            let roll = rng.sample(rules);
+            turns += 1;
+            if let Some(outcome) = game.apply(rules, roll, players[player], rng) {
+                return Ending {
+                    outcome,
+                    turns,
+                    player,
+                };
+            }
+        }
+    }
+}
+
+/// What one seat did over a batch of games.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerTally {
+    /// Games won on this player's roll: they picked the last fruit.
+    pub winning_picks: u64,
+    /// Games lost on this player's roll: they moved the bird into the orchard.
+    pub final_bird_moves: u64,
+    /// Rolls this player made, across every game.
+    pub turns: u64,
+}
+
+/// The team's results over a batch of games, along with each player's part in them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TeamTally {
+    pub team: Tally,
+    /// One entry per seat, in turn order.
+    pub players: Vec<PlayerTally>,
+}
+
+impl TeamTally {
+    /// No games yet, for a team of `seats` players.
+    pub fn new(seats: usize) -> TeamTally {
+        TeamTally {
+            team: Tally::default(),
+            players: vec![PlayerTally::default(); seats],
+        }
+    }
+
+    pub fn record(mut self, ending: Ending) -> TeamTally {
+        self.team = self.team.record((ending.outcome, ending.turns));
+        let finisher = &mut self.players[ending.player];
+        match ending.outcome {
+            Outcome::Won => finisher.winning_picks += 1,
+            Outcome::Lost => finisher.final_bird_moves += 1,
+        }
+        // Turns go round the table, so the first `turns % seats` seats got one extra roll.
+        let turns = u64::from(ending.turns);
+        let seats = self.players.len() as u64;
+        for (seat, player) in (0..).zip(&mut self.players) {
+            player.turns += turns / seats + u64::from(seat < turns % seats);
+        }
+        self
+    }
+
+    pub fn merge(mut self, other: TeamTally) -> TeamTally {
+        for (player, other) in self.players.iter_mut().zip(other.players) {
+            player.winning_picks += other.winning_picks;
+            player.final_bird_moves += other.final_bird_moves;
+            player.turns += other.turns;
+        }
+        self.team = self.team.merge(other.team);
+        self
+    }
+}
+
+/// Plays the games numbered `games` with `players` taking turns, like `simulate::play_games`.
+pub fn play_team_games(
+    rules: &Rules,
+    players: &[&dyn BasketStrategy],
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+) -> TeamTally {
+    play_chunks_from(
+        games,
+        seed,
+        progress,
+        || TeamTally::new(players.len()),
+        |tally: TeamTally, rng| tally.record(Game::full_game_with_players(rules, players, rng)),
+        TeamTally::merge,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+    use crate::simulate::play_games;
+    use crate::strategy::{LargestFirst, SmallestFirst};
+
+    #[test]
+    fn every_game_ends_on_someones_roll() {
+        let rules = Rules::default();
+        let players: [&dyn BasketStrategy; 3] = [&LargestFirst, &SmallestFirst, &LargestFirst];
+        let tally = play_team_games(&rules, &players, 0..20_000, 5, &Silent);
+        // Every seat has a tally, even before any games are played.
+        let none = play_team_games(&rules, &players, 0..0, 5, &Silent);
+        assert_eq!(none.players, [PlayerTally::default(); 3]);
+
+        let winning_picks: u64 = tally.players.iter().map(|p| p.winning_picks).sum();
+        let final_bird_moves: u64 = tally.players.iter().map(|p| p.final_bird_moves).sum();
+        assert_eq!(winning_picks, tally.team.won);
+        assert_eq!(final_bird_moves, tally.team.lost);
+
+        let turns: u64 = tally.players.iter().map(|p| p.turns).sum();
+        let histogram = tally.team.lengths.histogram(None);
+        let total: u64 = (0..)
+            .zip(histogram)
+            .map(|(turns, count)| turns * count)
+            .sum();
+        assert_eq!(turns, total);
+        // The first player never rolls less than the others.
+        assert!(tally
+            .players
+            .iter()
+            .all(|p| p.turns <= tally.players[0].turns));
+    }
+
+    #[test]
+    fn one_player_is_the_plain_game() {
+        let rules = Rules::default();
+        let games = 0..10_000;
+        let team = play_team_games(&rules, &[&LargestFirst], games.clone(), 9, &Silent);
+        let plain = play_games(&rules, &LargestFirst, games, 9, &Silent);
+        assert_eq!(team.team, plain);
+    }
+}
@@ -0,0 +1,338 @@
+//! The basket policy that maximizes the chance of winning, found by value iteration.
+//!
+//! Every roll that changes the game removes an apple or moves the bird, so ordering states by how
+//! much is left to happen puts every successor before its predecessors. Sweeping the Bellman update
+//! in that order (after folding away the "empty orchard" self-loops exactly as the solver does)
+//! means value iteration converges in a single pass, and the values it finds are exact.
+//!
+//! As long as the die treats every color alike, relabelling the colors never changes how good a
+//! state is for the best player, so the policy only solves states in their canonical form (see
+//! `Game::canonical`).
+
+use std::collections::{HashMap, HashSet};
+
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+use rand::RngCore;
+
+use crate::rules::Rules;
+use crate::strategy::{BasketStrategy, LargestFirst};
+use crate::{DieRoll, Game, Outcome};
+
+/// Optimal win probabilities and basket choices for every state reachable from some start.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    rules: Rules,
+    /// Whether states are solved in their canonical form.
+    symmetric: bool,
+    /// Win probability of each (canonical) state.
+    values: HashMap<Game, BigRational>,
+    /// Best orchard for each (canonical) state, and each number of picks left on the basket.
+    choices: HashMap<(Game, u8), usize>,
+}
+
+/// A state where some strategy's basket choice gives up some chance of winning.
+#[derive(Debug, Clone)]
+pub struct Mistake {
+    pub game: Game,
+    pub picks_left: u8,
+    pub chosen: Vec<usize>,
+    pub best: usize,
+    /// How much lower the win probability is after the strategy's choice than after the best one.
+    pub cost: BigRational,
+}
+
+/// Rolls left before the game must be over, if every one of them made progress.
+fn remaining(game: &Game) -> u32 {
+    u32::from(game.bird_position) + game.orchards.iter().map(|&n| u32::from(n)).sum::<u32>()
+}
+
+/// `game` in its canonical form, if states are being solved in that form.
+fn key(symmetric: bool, game: Game) -> Game {
+    if symmetric {
+        game.canonical()
+    } else {
+        game
+    }
+}
+
+/// Every unfinished state reachable from `start`, whatever the basket choices, put in its
+/// canonical form by `key`.
+fn reachable(start: Game, key: impl Fn(Game) -> Game) -> Vec<Game> {
+    let start = key(start);
+    let mut seen = HashSet::from([start]);
+    let mut frontier = vec![start];
+    while let Some(game) = frontier.pop() {
+        let mut bird = game;
+        bird.move_bird();
+        let picks = (0..game.orchards.len()).map(|orchard| {
+            let mut next = game;
+            next.pick(orchard);
+            next
+        });
+        for next in picks.chain([bird]).map(&key) {
+            if next.outcome().is_none() && seen.insert(next) {
+                frontier.push(next);
+            }
+        }
+    }
+    seen.into_iter().collect()
+}
+
+impl Policy {
+    /// Solves every state reachable from the start the rules describe.
+    ///
+    /// Values don't depend on where the game started, so the policy for a long bird track also
+    /// covers every shorter one with otherwise identical rules.
+    pub fn optimal(rules: &Rules) -> Policy {
+        Policy::optimal_from(rules, Game::new(rules))
+    }
+
+    /// Solves every state reachable from `start`, which can be any state of a game played by
+    /// `rules` (whatever its bird position or apples left).
+    pub fn optimal_from(rules: &Rules, start: Game) -> Policy {
+        let symmetric = rules.colors_alike();
+        let mut states = reachable(start, |game| key(symmetric, game));
+        states.sort_by_key(remaining);
+
+        let mut policy = Policy {
+            rules: rules.clone(),
+            symmetric,
+            values: HashMap::with_capacity(states.len()),
+            choices: HashMap::with_capacity(states.len()),
+        };
+        for game in states {
+            policy.update(game);
+        }
+        policy
+    }
+
+    /// The Bellman update for one state, whose successors must all be solved already.
+    fn update(&mut self, game: Game) {
+        let mut basket = BigRational::zero();
+        for picks_left in 1..=self.rules.basket_picks {
+            let choice_values = self.choice_values(&game, picks_left);
+            // Unwrap: unfinished games always have an orchard to pick from.
+            let best_value = choice_values.iter().map(|(_, value)| value).max().unwrap();
+            // Ties go to largest-first, so the table only departs from it where that matters.
+            let largest = LargestFirst.choices(&game, picks_left)[0];
+            let best = choice_values
+                .iter()
+                .find(|(orchard, value)| *orchard == largest && value == best_value)
+                .or_else(|| choice_values.iter().find(|(_, value)| value == best_value))
+                .map(|(orchard, _)| *orchard)
+                // Unwrap: `best_value` came from this list.
+                .unwrap();
+            basket = best_value.clone();
+            self.choices.insert((game, picks_left), best);
+        }
+
+        let mut wins = BigRational::zero();
+        let mut moving_weight = 0;
+        for (roll, weight) in self.rules.die() {
+            let mut next = game;
+            let value = match roll {
+                DieRoll::Fruit(orchard) => {
+                    let outcome = next.pick(orchard);
+                    if next == game {
+                        continue;
+                    }
+                    self.value(&next, outcome)
+                }
+                DieRoll::Basket => basket.clone(),
+                DieRoll::Bird => {
+                    let outcome = next.move_bird();
+                    self.value(&next, outcome)
+                }
+            };
+            wins += value * BigRational::from_integer(weight.into());
+            moving_weight += weight;
+        }
+
+        self.values
+            .insert(game, wins / BigRational::from_integer(moving_weight.into()));
+    }
+
+    fn value(&self, game: &Game, outcome: Option<Outcome>) -> BigRational {
+        match outcome {
+            Some(Outcome::Won) => BigRational::one(),
+            Some(Outcome::Lost) => BigRational::zero(),
+            None => self.values[&key(self.symmetric, *game)].clone(),
+        }
+    }
+
+    /// Win probability after taking a fruit from each orchard that still has one, with
+    /// `picks_left` fruit (including that one) still to take from the basket and the rest taken
+    /// as well as possible.
+    pub fn choice_values(&self, game: &Game, picks_left: u8) -> Vec<(usize, BigRational)> {
+        (0..game.orchards.len())
+            .filter(|&orchard| game.orchards[orchard] > 0)
+            .map(|orchard| {
+                let mut next = *game;
+                let value = match next.pick(orchard) {
+                    None if picks_left > 1 => self
+                        .choice_values(&next, picks_left - 1)
+                        .into_iter()
+                        .map(|(_, value)| value)
+                        .max()
+                        // Unwrap: the game isn't over, so some orchard has fruit left.
+                        .unwrap(),
+                    outcome => self.value(&next, outcome),
+                };
+                (orchard, value)
+            })
+            .collect()
+    }
+
+    /// Optimal win probability from `game`, if it was reachable from the policy's start.
+    pub fn win_probability(&self, game: &Game) -> Option<&BigRational> {
+        self.values.get(&key(self.symmetric, *game))
+    }
+
+    /// The best orchard to pick from in `game`, with `picks_left` fruit to take from the basket.
+    pub fn choice(&self, game: &Game, picks_left: u8) -> Option<usize> {
+        let canonical = key(self.symmetric, *game);
+        let best = *self.choices.get(&(canonical, picks_left))?;
+        if !self.symmetric {
+            return Some(best);
+        }
+        // Any orchard with as many apples left is just as good; like largest-first, take the
+        // last of them.
+        let apples = canonical.orchards[best];
+        (0..game.orchards.len())
+            .rev()
+            .find(|&orchard| game.orchards[orchard] == apples)
+    }
+
+    /// Every (canonical) state and number of picks left with its best basket choice, starting with
+    /// the fullest orchards.
+    pub fn table(&self) -> Vec<(Game, u8, usize)> {
+        let mut table: Vec<_> = self
+            .choices
+            .iter()
+            .map(|(&(game, picks_left), &choice)| (game, picks_left, choice))
+            .collect();
+        table.sort_by(|(a, a_picks, _), (b, b_picks, _)| {
+            b.bird_position
+                .cmp(&a.bird_position)
+                .then_with(|| b.orchards.cmp(&a.orchards))
+                .then_with(|| b_picks.cmp(a_picks))
+        });
+        table
+    }
+
+    /// Every (canonical) state where `strategy` picks worse than this policy would, worst first.
+    ///
+    /// Strategies that don't treat colors alike may also make mistakes in states that are only
+    /// relabellings of these, which aren't listed.
+    pub fn mistakes(&self, strategy: &dyn BasketStrategy) -> Vec<Mistake> {
+        let mut mistakes: Vec<Mistake> = self
+            .choices
+            .iter()
+            .filter_map(|(&(game, picks_left), &best)| {
+                let values: HashMap<_, _> =
+                    self.choice_values(&game, picks_left).into_iter().collect();
+                let chosen = strategy.choices(&game, picks_left);
+                let chosen_value = chosen
+                    .iter()
+                    .map(|orchard| &values[orchard])
+                    .sum::<BigRational>()
+                    / BigRational::from_integer(chosen.len().into());
+                let cost = &values[&best] - chosen_value;
+                (cost > BigRational::zero()).then_some(Mistake {
+                    game,
+                    picks_left,
+                    chosen,
+                    best,
+                    cost,
+                })
+            })
+            .collect();
+        mistakes.sort_by(|a, b| b.cost.cmp(&a.cost));
+        mistakes
+    }
+}
+
+impl BasketStrategy for Policy {
+    fn choose(&self, game: &Game, picks_left: u8, _rng: &mut dyn RngCore) -> usize {
+        // Unwrap: the simulator only visits states reachable from its start, which must be the
+        // start (or harder) that the policy was computed from.
+        self.choice(game, picks_left).unwrap()
+    }
+
+    fn choices(&self, game: &Game, picks_left: u8) -> Vec<usize> {
+        // Unwrap: as above, for the solver.
+        vec![self.choice(game, picks_left).unwrap()]
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.symmetric
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Solver;
+    use crate::strategy::{ColorOrder, Favourite, Random, SmallestFirst};
+
+    fn rules(track_length: u8, apples: u8) -> Rules {
+        Rules {
+            apples,
+            track_length,
+            ..Rules::default()
+        }
+    }
+
+    #[test]
+    fn optimal_beats_every_strategy() {
+        let policy = Policy::optimal(&rules(6, 4));
+        let strategies: [&dyn BasketStrategy; 5] = [
+            &LargestFirst,
+            &SmallestFirst,
+            &Random,
+            &ColorOrder(vec![0, 1, 2, 3]),
+            &Favourite(0),
+        ];
+        for strategy in strategies {
+            for track_length in 1..=6 {
+                let rules = rules(track_length, 4);
+                let exact = Solver::new(&rules, strategy).solve().win_probability;
+                let optimal = policy.win_probability(&Game::new(&rules)).unwrap();
+                assert!(exact <= *optimal);
+            }
+        }
+    }
+
+    #[test]
+    fn solver_agrees_with_policy() {
+        let policy = Policy::optimal(&rules(5, 3));
+        for track_length in 1..=5 {
+            let rules = rules(track_length, 3);
+            assert_eq!(
+                Solver::new(&rules, &policy).solve().win_probability,
+                *policy.win_probability(&Game::new(&rules)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn solver_agrees_with_policy_picking_two() {
+        let rules = Rules {
+            apples: 3,
+            track_length: 5,
+            ..Rules::orchard()
+        };
+        let policy = Policy::optimal(&rules);
+        assert_eq!(
+            Solver::new(&rules, &policy).solve().win_probability,
+            *policy.win_probability(&Game::new(&rules)).unwrap()
+        );
+    }
+
+    #[test]
+    fn smallest_first_makes_mistakes() {
+        let policy = Policy::optimal(&rules(4, 2));
+        assert!(!policy.mistakes(&SmallestFirst).is_empty());
+    }
+}
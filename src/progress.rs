@@ -0,0 +1,227 @@
+//! Progress reports from long simulations, for whoever is watching: an indicatif bar on the
+//! command line, nobody at all, or a callback that a GUI or a log pipeline subscribes to.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::stats::Interval;
+
+/// Something that wants to hear how a simulation is getting on.
+///
+/// Simulations report from many threads at once, a chunk of games at a time.
+pub trait Progress: Sync {
+    /// The run now expects to play `games` games in all; it may change its mind as it goes.
+    fn set_length(&self, games: u64);
+
+    /// Another `games` games have been played, `won` of them won if the simulation counts wins.
+    fn played(&self, games: u64, won: Option<u64>);
+
+    /// The run is over.
+    fn finish(&self);
+}
+
+/// Reports to nobody.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent;
+
+impl Progress for Silent {
+    fn set_length(&self, _games: u64) {}
+
+    fn played(&self, _games: u64, _won: Option<u64>) {}
+
+    fn finish(&self) {}
+}
+
+impl Progress for ProgressBar {
+    fn set_length(&self, games: u64) {
+        ProgressBar::set_length(self, games);
+    }
+
+    fn played(&self, games: u64, _won: Option<u64>) {
+        self.inc(games);
+    }
+
+    fn finish(&self) {
+        ProgressBar::finish(self);
+    }
+}
+
+/// An indicatif bar that also shows the win rate so far, and its (Wilson) confidence interval,
+/// updating as each chunk of games finishes.
+pub struct WinRateBar {
+    bar: ProgressBar,
+    confidence: f64,
+    /// Games counted so far, and how many of them were won.
+    tally: Mutex<(u64, u64)>,
+}
+
+impl WinRateBar {
+    pub fn new(bar: ProgressBar, confidence: f64) -> WinRateBar {
+        // Unwrap: the template is fixed, and valid.
+        let style = ProgressStyle::with_template("{wide_bar} {pos}/{len} ({eta}) {msg}").unwrap();
+        WinRateBar {
+            bar: bar.with_style(style),
+            confidence,
+            tally: Mutex::new((0, 0)),
+        }
+    }
+}
+
+impl Progress for WinRateBar {
+    fn set_length(&self, games: u64) {
+        self.bar.set_length(games);
+    }
+
+    fn played(&self, games: u64, won: Option<u64>) {
+        if let Some(won) = won {
+            // Unwrap: nothing panics while holding the lock.
+            let mut tally = self.tally.lock().unwrap();
+            tally.0 += games;
+            tally.1 += won;
+            let (counted, won) = *tally;
+            let interval = Interval::wilson(won, counted, self.confidence);
+            self.bar.set_message(format!(
+                "win rate {:.4}% ({}% CI {:.4}% to {:.4}%)",
+                100.0 * won as f64 / counted as f64,
+                100.0 * self.confidence,
+                100.0 * interval.low,
+                100.0 * interval.high
+            ));
+        }
+        self.bar.inc(games);
+    }
+
+    fn finish(&self) {
+        self.bar.finish();
+    }
+}
+
+/// Where a run has got to, as passed to a `Callback`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshot {
+    pub played: u64,
+    /// Games the run expects to play in all.
+    pub length: u64,
+    /// Games played whose outcomes were reported, and how many of those were won.
+    pub counted: u64,
+    pub won: u64,
+    pub elapsed: Duration,
+    pub finished: bool,
+}
+
+impl Snapshot {
+    /// The win rate so far, once any outcomes have been reported.
+    pub fn win_rate(&self) -> Option<f64> {
+        (self.counted > 0).then(|| self.won as f64 / self.counted as f64)
+    }
+
+    /// The (Wilson) confidence interval on the win rate so far.
+    pub fn interval(&self, confidence: f64) -> Option<Interval> {
+        (self.counted > 0).then(|| Interval::wilson(self.won, self.counted, confidence))
+    }
+
+    /// How much longer the rest of the run should take at the pace so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.played == 0 {
+            return None;
+        }
+        let left = self.length.saturating_sub(self.played) as f64 / self.played as f64;
+        Some(self.elapsed.mul_f64(left))
+    }
+}
+
+/// Calls a function with a fresh `Snapshot` every time the run reports.
+///
+/// Reports are made one at a time, in order, from whichever thread played the games.
+pub struct Callback<F> {
+    report: F,
+    started: Instant,
+    snapshot: Mutex<Snapshot>,
+}
+
+impl<F: Fn(&Snapshot) + Sync + Send> Callback<F> {
+    pub fn new(report: F) -> Callback<F> {
+        Callback {
+            report,
+            started: Instant::now(),
+            snapshot: Mutex::new(Snapshot::default()),
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Snapshot)) {
+        // Unwrap: only a panicking callback could poison the lock, and that panic propagates.
+        let mut snapshot = self.snapshot.lock().unwrap();
+        update(&mut snapshot);
+        snapshot.elapsed = self.started.elapsed();
+        (self.report)(&snapshot);
+    }
+}
+
+impl<F: Fn(&Snapshot) + Sync + Send> Progress for Callback<F> {
+    fn set_length(&self, games: u64) {
+        self.update(|snapshot| snapshot.length = games);
+    }
+
+    fn played(&self, games: u64, won: Option<u64>) {
+        self.update(|snapshot| {
+            snapshot.played += games;
+            if let Some(won) = won {
+                snapshot.counted += games;
+                snapshot.won += won;
+            }
+        });
+    }
+
+    fn finish(&self) {
+        self.update(|snapshot| snapshot.finished = true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callbacks_see_a_running_win_rate() {
+        let snapshots = Mutex::new(Vec::new());
+        let progress = Callback::new(|snapshot: &Snapshot| {
+            snapshots.lock().unwrap().push(*snapshot);
+        });
+        progress.set_length(100);
+        progress.played(40, Some(10));
+        progress.played(10, None);
+        progress.finish();
+
+        let snapshots = snapshots.into_inner().unwrap();
+        assert_eq!(snapshots.len(), 4);
+        assert_eq!(snapshots[0].win_rate(), None);
+        assert_eq!(snapshots[0].eta(), None);
+        assert_eq!(snapshots[1].win_rate(), Some(0.25));
+        let last = snapshots[3];
+        assert!(last.finished);
+        assert_eq!((last.played, last.counted, last.won), (50, 40, 10));
+        assert!(last.interval(0.95).unwrap().low < 0.25);
+        // Half the games are left, so it should take as long again.
+        let eta = last.eta().unwrap().as_secs_f64();
+        assert!((eta - last.elapsed.as_secs_f64()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_bar_shows_the_win_rate_so_far() {
+        let progress = WinRateBar::new(ProgressBar::hidden(), 0.95);
+        progress.set_length(2000);
+        progress.played(500, None);
+        assert_eq!(progress.bar.message(), "");
+        progress.played(1000, Some(250));
+        progress.played(500, Some(250));
+        assert_eq!(progress.bar.position(), 2000);
+        // 500 out of 1500, whose Wilson interval is 30.99% to 35.76%.
+        let message = progress.bar.message();
+        assert!(
+            message.starts_with("win rate 33.3333% (95% CI 30.9"),
+            "{message}"
+        );
+    }
+}
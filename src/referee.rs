@@ -0,0 +1,265 @@
+//! The program as die and referee for a real game at the table.
+//!
+//! Each turn it either rolls for the players or takes the roll they made with a physical die,
+//! shows the bird and the trees, and keeps score. When the basket comes up it asks which fruit to
+//! take, showing the chance of winning after each choice. Every state update goes through
+//! `Game::apply`, with the players' answers standing in for a basket strategy.
+
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+
+use num_traits::ToPrimitive;
+use rand::{Rng, RngCore};
+
+use crate::policy::Policy;
+use crate::rules::Rules;
+use crate::strategy::BasketStrategy;
+use crate::{DieRoll, Game, Outcome, COLOR_NAMES};
+
+/// Where the players type, and where the referee answers.
+struct Terminal<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Terminal<R, W> {
+    /// Asks a question, returning the trimmed answer, or `None` once the input has run out.
+    fn ask(&mut self, question: &str) -> io::Result<Option<String>> {
+        write!(self.output, "{question} ")?;
+        self.output.flush()?;
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(answer.trim().to_lowercase()))
+    }
+}
+
+/// The value whose name `answer` starts, if it's the only one.
+fn parse_name<T: Copy>(answer: &str, names: &[(String, T)]) -> Result<T, String> {
+    if let Some(&(_, value)) = names.iter().find(|(name, _)| name == answer) {
+        return Ok(value);
+    }
+    let matches: Vec<_> = names
+        .iter()
+        .filter(|(name, _)| name.starts_with(answer))
+        .collect();
+    match matches[..] {
+        [&(_, value)] => Ok(value),
+        [] => Err(format!("'{answer}' isn't one of the choices")),
+        _ => Err(format!("'{answer}' could be more than one of the choices")),
+    }
+}
+
+fn percent(probability: Option<f64>) -> String {
+    match probability {
+        Some(probability) => format!("{:.2}%", 100.0 * probability),
+        None => "?".to_owned(),
+    }
+}
+
+/// Asks the players what to take each time the basket comes up.
+struct Players<'a, R, W> {
+    policy: &'a Policy,
+    terminal: &'a Mutex<Terminal<R, W>>,
+}
+
+impl<R: BufRead + Send, W: Write + Send> Players<'_, R, W> {
+    fn ask(&self, game: &Game, picks_left: u8) -> io::Result<usize> {
+        // Unwrap: nothing panics while holding the lock.
+        let mut terminal = self.terminal.lock().unwrap();
+        let values = self.policy.choice_values(game, picks_left);
+        // Unwrap: the policy only fails to cover states the game can't reach.
+        let best = self.policy.choice(game, picks_left).unwrap();
+        let choices: Vec<String> = values
+            .iter()
+            .map(|(orchard, value)| {
+                format!("{} {}", COLOR_NAMES[*orchard], percent(value.to_f64()))
+            })
+            .collect();
+        writeln!(
+            terminal.output,
+            "Basket! Take any fruit ({} to take). Chance of winning after each: {}",
+            picks_left,
+            choices.join(", ")
+        )?;
+        let names: Vec<_> = values
+            .iter()
+            .map(|&(orchard, _)| (COLOR_NAMES[orchard].to_owned(), orchard))
+            .collect();
+        loop {
+            let question = format!("Which fruit? (enter for {})", COLOR_NAMES[best]);
+            match terminal.ask(&question)?.as_deref() {
+                // With nobody left to ask, make the best choice.
+                None | Some("") => return Ok(best),
+                Some(answer) => match parse_name(answer, &names) {
+                    Ok(orchard) => return Ok(orchard),
+                    Err(message) => writeln!(terminal.output, "{message}")?,
+                },
+            }
+        }
+    }
+}
+
+impl<R: BufRead + Send, W: Write + Send> BasketStrategy for Players<'_, R, W> {
+    fn choose(&self, game: &Game, picks_left: u8, _rng: &mut dyn RngCore) -> usize {
+        // If the terminal has gone away there's nobody left to ask, so play on as well as possible.
+        self.ask(game, picks_left)
+            .unwrap_or_else(|_| self.policy.choice(game, picks_left).unwrap())
+    }
+
+    fn choices(&self, game: &Game, picks_left: u8) -> Vec<usize> {
+        self.policy.choices(game, picks_left)
+    }
+}
+
+/// The bird's path and every tree, as a few lines of text.
+fn board(rules: &Rules, game: &Game) -> String {
+    let walked = usize::from(rules.track_length.saturating_sub(game.bird_position));
+    let mut board = format!(
+        "Bird   {}B{}| {} steps from the orchard\n",
+        "-".repeat(walked),
+        ".".repeat(usize::from(game.bird_position)),
+        game.bird_position
+    );
+    for (orchard, &apples) in game.orchards.iter().enumerate() {
+        let picked = usize::from(rules.apples.saturating_sub(apples));
+        board += &format!(
+            "{:<6} {}{}\n",
+            COLOR_NAMES[orchard],
+            "*".repeat(usize::from(apples)),
+            ".".repeat(picked)
+        );
+    }
+    board
+}
+
+/// Referees one game, reading rolls and basket choices from `input` and reporting to `output`.
+///
+/// Returns how the game ended, or `None` if the players quit (or the input ran out) first.
+pub fn play(
+    rules: &Rules,
+    policy: &Policy,
+    input: impl BufRead + Send,
+    output: impl Write + Send,
+    rng: &mut impl Rng,
+) -> io::Result<Option<Outcome>> {
+    let terminal = Mutex::new(Terminal { input, output });
+    let players = Players {
+        policy,
+        terminal: &terminal,
+    };
+    let mut rolls: Vec<(String, DieRoll)> = (0..usize::from(rules.colors))
+        .map(|orchard| (COLOR_NAMES[orchard].to_owned(), DieRoll::Fruit(orchard)))
+        .collect();
+    if rules.basket_faces > 0 {
+        rolls.push(("basket".to_owned(), DieRoll::Basket));
+    }
+    if rules.bird_faces > 0 {
+        rolls.push(("bird".to_owned(), DieRoll::Bird));
+    }
+
+    let mut game = Game::new(rules);
+    loop {
+        let roll = {
+            // Unwrap: nothing panics while holding the lock.
+            let mut terminal = terminal.lock().unwrap();
+            let odds = policy.win_probability(&game).and_then(ToPrimitive::to_f64);
+            writeln!(terminal.output)?;
+            write!(terminal.output, "{}", board(rules, &game))?;
+            writeln!(
+                terminal.output,
+                "Chance of winning from here, playing the basket well: {}",
+                percent(odds)
+            )?;
+            let question = "Roll? (enter to roll for you, or type what the die shows; q to quit)";
+            let roll = match terminal.ask(question)?.as_deref() {
+                None | Some("q") | Some("quit") => return Ok(None),
+                Some("") => rng.sample(rules),
+                Some(answer) => match parse_name(answer, &rolls) {
+                    Ok(roll) => roll,
+                    Err(message) => {
+                        writeln!(terminal.output, "{message}")?;
+                        continue;
+                    }
+                },
+            };
+            let rolled = match roll {
+                DieRoll::Fruit(orchard) => COLOR_NAMES[orchard],
+                DieRoll::Basket => "basket",
+                DieRoll::Bird => "bird",
+            };
+            writeln!(terminal.output, "Rolled {rolled}.")?;
+            if let DieRoll::Fruit(orchard) = roll {
+                if game.orchards[orchard] == 0 {
+                    writeln!(terminal.output, "That tree is bare; nothing happens.")?;
+                }
+            }
+            roll
+        };
+
+        if let Some(outcome) = game.apply(rules, roll, &players, rng) {
+            // Unwrap: as above.
+            let mut terminal = terminal.lock().unwrap();
+            write!(terminal.output, "{}", board(rules, &game))?;
+            match outcome {
+                Outcome::Won => writeln!(terminal.output, "Every fruit is picked: you win!")?,
+                Outcome::Lost => writeln!(terminal.output, "The bird reached the orchard first.")?,
+            }
+            return Ok(Some(outcome));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn referee(rules: &Rules, input: &str) -> (Option<Outcome>, String) {
+        let policy = Policy::optimal(rules);
+        let mut output = Vec::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        let outcome = play(rules, &policy, input.as_bytes(), &mut output, &mut rng).unwrap();
+        (outcome, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn typed_rolls_play_a_game() {
+        let rules = Rules {
+            colors: 2,
+            apples: 1,
+            track_length: 2,
+            ..Rules::default()
+        };
+        // "b" could be blue, basket or bird, but blue isn't in this game.
+        let (outcome, output) = referee(&rules, "bi\nx\nred\nba\ngreen\n");
+        assert_eq!(outcome, Some(Outcome::Won));
+        assert!(output.contains("isn't one of the choices"));
+        assert!(output.contains("Basket!"));
+
+        let (outcome, _) = referee(&rules, "bird\nbird\n");
+        assert_eq!(outcome, Some(Outcome::Lost));
+    }
+
+    #[test]
+    fn quitting_and_running_out() {
+        let rules = Rules::default();
+        assert_eq!(referee(&rules, "q\n").0, None);
+        assert_eq!(referee(&rules, "red\n").0, None);
+    }
+
+    #[test]
+    fn names_by_unique_prefix() {
+        let names = [
+            ("blue".to_owned(), 2),
+            ("basket".to_owned(), 4),
+            ("bird".to_owned(), 5),
+        ];
+        assert_eq!(parse_name("bl", &names), Ok(2));
+        assert_eq!(parse_name("bird", &names), Ok(5));
+        assert!(parse_name("b", &names).is_err());
+        assert!(parse_name("green", &names).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Results in a form other programs can read: one flat record per run, as CSV, JSON or NDJSON.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::rules::Rules;
+use crate::simulate::Tally;
+use crate::solver::Solution;
+
+/// Everything there is to know about one run: what was played, and how it went.
+///
+/// Runs that were only solved exactly, not simulated, have no seed or games, and their win rate
+/// and interval are all the exact win rate.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    /// Name of the published game the rules started from.
+    pub game: String,
+    pub rules: Rules,
+    pub strategy: String,
+    pub seed: Option<u64>,
+    pub games: u64,
+    pub won: u64,
+    pub lost: u64,
+    pub win_rate: f64,
+    /// Confidence level of the interval `ci_low` to `ci_high`.
+    pub confidence: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+    pub mean_turns: Option<f64>,
+    pub median_turns: Option<u32>,
+    /// The exact win probability as a fraction, e.g. "2/3", when it was solved for.
+    pub exact_win_probability: Option<String>,
+    pub exact_win_rate: Option<f64>,
+    pub exact_mean_turns: Option<f64>,
+}
+
+impl Record {
+    pub fn new(
+        game: &str,
+        rules: &Rules,
+        strategy: &str,
+        seed: u64,
+        tally: &Tally,
+        confidence: f64,
+    ) -> Record {
+        let interval = tally.interval(confidence);
+        Record {
+            game: game.to_owned(),
+            rules: rules.clone(),
+            strategy: strategy.to_owned(),
+            seed: Some(seed),
+            games: tally.won + tally.lost,
+            won: tally.won,
+            lost: tally.lost,
+            win_rate: tally.win_rate(),
+            confidence,
+            ci_low: interval.low,
+            ci_high: interval.high,
+            mean_turns: tally.lengths.mean(None),
+            median_turns: tally.lengths.median(None),
+            exact_win_probability: None,
+            exact_win_rate: None,
+            exact_mean_turns: None,
+        }
+    }
+
+    /// A run that was solved exactly instead of simulated.
+    pub fn exact(game: &str, rules: &Rules, strategy: &str, exact: &Solution) -> Record {
+        let win_rate = exact.win_rate();
+        Record {
+            game: game.to_owned(),
+            rules: rules.clone(),
+            strategy: strategy.to_owned(),
+            seed: None,
+            games: 0,
+            won: 0,
+            lost: 0,
+            win_rate,
+            confidence: 1.0,
+            ci_low: win_rate,
+            ci_high: win_rate,
+            mean_turns: Some(exact.mean_turns()),
+            median_turns: None,
+            exact_win_probability: None,
+            exact_win_rate: None,
+            exact_mean_turns: None,
+        }
+        .with_exact(exact)
+    }
+
+    /// Adds the exact solution for the same rules and strategy.
+    pub fn with_exact(mut self, exact: &Solution) -> Record {
+        self.exact_win_probability = Some(exact.win_probability.to_string());
+        self.exact_win_rate = Some(exact.win_rate());
+        self.exact_mean_turns = Some(exact.mean_turns());
+        self
+    }
+
+    /// Column names for `csv_row`, with the rules flattened into their own columns.
+    pub const CSV_HEADER: &'static str = "game,bird_position,colors,apples,bird_faces,\
+        basket_faces,basket_picks,face_weights,strategy,seed,games,won,lost,win_rate,confidence,\
+        ci_low,ci_high,mean_turns,median_turns,exact_win_probability,exact_win_rate,\
+        exact_mean_turns";
+
+    /// The record as one line of CSV (without the newline); missing values are left empty.
+    pub fn csv_row(&self) -> String {
+        fn optional(value: &Option<impl ToString>) -> String {
+            value.as_ref().map(ToString::to_string).unwrap_or_default()
+        }
+        let rules = &self.rules;
+        [
+            self.game.clone(),
+            rules.track_length.to_string(),
+            rules.colors.to_string(),
+            rules.apples.to_string(),
+            rules.bird_faces.to_string(),
+            rules.basket_faces.to_string(),
+            rules.basket_picks.to_string(),
+            // Space separated, to stay in one column; empty for a fair die.
+            rules
+                .face_weights
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            self.strategy.clone(),
+            optional(&self.seed),
+            self.games.to_string(),
+            self.won.to_string(),
+            self.lost.to_string(),
+            self.win_rate.to_string(),
+            self.confidence.to_string(),
+            self.ci_low.to_string(),
+            self.ci_high.to_string(),
+            optional(&self.mean_turns),
+            optional(&self.median_turns),
+            optional(&self.exact_win_probability),
+            optional(&self.exact_win_rate),
+            optional(&self.exact_mean_turns),
+        ]
+        .join(",")
+    }
+}
+
+/// The machine-readable output formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A header line, then one comma-separated line per record.
+    Csv,
+    /// A single JSON array holding every record, written once the last one is in.
+    Json,
+    /// One JSON object per line, written as soon as each record is.
+    Ndjson,
+}
+
+/// Writes records to `out` in some format, streaming them where the format allows.
+#[derive(Debug)]
+pub struct RecordWriter<W: Write> {
+    out: W,
+    format: Format,
+    /// Records held back until `finish`, for formats that can't be written a piece at a time.
+    pending: Vec<Record>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(mut out: W, format: Format) -> io::Result<RecordWriter<W>> {
+        if format == Format::Csv {
+            writeln!(out, "{}", Record::CSV_HEADER)?;
+        }
+        Ok(RecordWriter {
+            out,
+            format,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, record: Record) -> io::Result<()> {
+        match self.format {
+            Format::Csv => writeln!(self.out, "{}", record.csv_row())?,
+            Format::Json => self.pending.push(record),
+            Format::Ndjson => {
+                serde_json::to_writer(&mut self.out, &record)?;
+                writeln!(self.out)?;
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Writes anything held back, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == Format::Json {
+            serde_json::to_writer_pretty(&mut self.out, &self.pending)?;
+            writeln!(self.out)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    fn record() -> Record {
+        let tally = Tally::default()
+            .record((Outcome::Won, 20))
+            .record((Outcome::Lost, 10))
+            .record((Outcome::Won, 24));
+        Record::new(
+            "first-orchard",
+            &Rules::default(),
+            "largest",
+            7,
+            &tally,
+            0.95,
+        )
+    }
+
+    fn written(format: Format, records: &[Record]) -> String {
+        let mut writer = RecordWriter::new(Vec::new(), format).unwrap();
+        for record in records {
+            writer.write(record.clone()).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_rows_match_the_header() {
+        let csv = written(Format::Csv, &[record(), record()]);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], Record::CSV_HEADER);
+        let columns = Record::CSV_HEADER.split(',').count();
+        assert!(lines[1..]
+            .iter()
+            .all(|line| line.split(',').count() == columns));
+        assert!(lines[1].starts_with("first-orchard,5,4,4,1,1,1,,largest,7,3,2,1,"));
+    }
+
+    #[test]
+    fn json_and_ndjson_carry_the_same_records() {
+        let records = [record(), record()];
+        let json: serde_json::Value =
+            serde_json::from_str(&written(Format::Json, &records)).unwrap();
+        let ndjson: Vec<serde_json::Value> = written(Format::Ndjson, &records)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(json, serde_json::Value::Array(ndjson.clone()));
+        assert_eq!(ndjson[0]["rules"]["track_length"], 5);
+        assert_eq!(ndjson[0]["won"], 2);
+        assert_eq!(ndjson[0]["exact_win_rate"], serde_json::Value::Null);
+    }
+}
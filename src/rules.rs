@@ -0,0 +1,179 @@
+//! The parts of the game's setup that differ between editions and house rules.
+
+use rand::distributions::{Distribution, Uniform};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{DieRoll, MAX_COLORS};
+
+/// How the game is set up: the fruit on the trees, the faces on the die, and how far the bird has
+/// to go.
+///
+/// The default is First Orchard as it comes in the box, played on the 'normal' difficulty; see
+/// `Rules::orchard` for the classic game.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Rules {
+    /// Number of fruit colors, each with its own tree and its own face on the die.
+    pub colors: u8,
+
+    /// Fruit on each tree at the start of the game.
+    pub apples: u8,
+
+    /// Number of die faces that move the bird.
+    pub bird_faces: u8,
+
+    /// Number of die faces that let the players pick any fruit.
+    pub basket_faces: u8,
+
+    /// Fruit taken, one at a time and each of any color, for every basket rolled.
+    pub basket_picks: u8,
+
+    /// Steps the bird must take to reach the orchard, i.e. its starting `bird_position`.
+    pub track_length: u8,
+
+    /// How likely each face of the die is to come up, relative to the others and in the order of
+    /// `Rules::face`, for a die that isn't fair. Empty for a fair die.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub face_weights: Vec<u32>,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            colors: 4,
+            apples: 4,
+            bird_faces: 1,
+            basket_faces: 1,
+            basket_picks: 1,
+            track_length: 5,
+            face_weights: vec![],
+        }
+    }
+}
+
+impl Rules {
+    /// The classic Orchard (Obstgarten): ten fruit on each of four trees, a basket that picks two,
+    /// and a nine-piece raven puzzle, where the players lose once the last piece is laid.
+    ///
+    /// The raven plays the same part as First Orchard's bird, so it's modelled as a bird with a
+    /// nine-step track.
+    pub fn orchard() -> Rules {
+        Rules {
+            colors: 4,
+            apples: 10,
+            bird_faces: 1,
+            basket_faces: 1,
+            basket_picks: 2,
+            track_length: 9,
+            face_weights: vec![],
+        }
+    }
+
+    /// Total number of faces on the die.
+    pub fn faces(&self) -> u8 {
+        self.colors + self.basket_faces + self.bird_faces
+    }
+
+    /// What face number `face` of the die shows, counting fruit colors, then baskets, then birds.
+    pub fn face(&self, face: u8) -> DieRoll {
+        if face < self.colors {
+            DieRoll::Fruit(usize::from(face))
+        } else if face < self.colors + self.basket_faces {
+            DieRoll::Basket
+        } else {
+            DieRoll::Bird
+        }
+    }
+
+    /// How likely face number `face` is to come up, relative to the other faces.
+    pub fn weight(&self, face: u8) -> u32 {
+        self.face_weights
+            .get(usize::from(face))
+            .copied()
+            .unwrap_or(1)
+    }
+
+    /// The weights of every face added up, so that face `f` comes up with probability
+    /// `weight(f) / total_weight()`.
+    pub fn total_weight(&self) -> u32 {
+        (0..self.faces()).map(|face| self.weight(face)).sum()
+    }
+
+    /// Every face of the die with its weight; a roll may appear more than once.
+    pub fn die(&self) -> impl Iterator<Item = (DieRoll, u32)> + '_ {
+        (0..self.faces()).map(|face| (self.face(face), self.weight(face)))
+    }
+
+    /// Whether every fruit face is as likely as every other, so that the colors are
+    /// interchangeable (see `Game::canonical`).
+    pub fn colors_alike(&self) -> bool {
+        (1..self.colors).all(|face| self.weight(face) == self.weight(0))
+    }
+
+    /// An upper bound on the number of distinct unfinished states, which is what the exact solver
+    /// and the optimal policy have to visit.
+    pub fn state_count(&self) -> u64 {
+        u64::from(self.apples)
+            .saturating_add(1)
+            .saturating_pow(self.colors.into())
+            .saturating_mul(self.track_length.into())
+    }
+
+    /// Checks that these rules describe a game that can actually be played.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.colors == 0 || usize::from(self.colors) > MAX_COLORS {
+            return Err(format!("colors must be between 1 and {MAX_COLORS}"));
+        }
+        if self.apples == 0 {
+            return Err("each tree needs at least one apple".to_owned());
+        }
+        if self.basket_faces > 0 && self.basket_picks == 0 {
+            return Err("the basket must pick at least one fruit".to_owned());
+        }
+        if self.track_length == 0 {
+            return Err("the bird must start at least one step from the orchard".to_owned());
+        }
+        let faces =
+            u16::from(self.colors) + u16::from(self.basket_faces) + u16::from(self.bird_faces);
+        if faces > u16::from(u8::MAX) {
+            return Err(format!("the die can have at most {} faces", u8::MAX));
+        }
+        if !self.face_weights.is_empty() {
+            if self.face_weights.len() != usize::from(self.faces()) {
+                return Err(format!(
+                    "the die has {} faces, but {} face weights were given",
+                    self.faces(),
+                    self.face_weights.len()
+                ));
+            }
+            if self.face_weights.contains(&0) {
+                return Err("every face of the die needs a weight of at least 1".to_owned());
+            }
+            if self.face_weights.iter().map(|&w| u64::from(w)).sum::<u64>() > u64::from(u32::MAX) {
+                return Err(format!(
+                    "the face weights can add up to at most {}",
+                    u32::MAX
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rolls the die these rules describe.
+impl Distribution<DieRoll> for Rules {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> DieRoll {
+        if self.face_weights.is_empty() {
+            return self.face(Uniform::new(0, self.faces()).sample(rng));
+        }
+        // Faces take up consecutive runs of the total weight, each as long as its own weight.
+        let mut draw = Uniform::new(0, self.total_weight()).sample(rng);
+        for (face, &weight) in (0..).zip(&self.face_weights) {
+            if draw < weight {
+                return self.face(face);
+            }
+            draw -= weight;
+        }
+        unreachable!("draws are less than the total weight")
+    }
+}
@@ -0,0 +1,261 @@
+//! Monte Carlo estimates of the win rate, played in parallel from reproducible random streams.
+
+use std::ops::Range;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use rayon::prelude::*;
+
+use crate::progress::Progress;
+use crate::rules::Rules;
+use crate::stats::{trials_for_half_width, GameLengths, Interval};
+use crate::strategy::BasketStrategy;
+use crate::{Game, Outcome};
+
+/// What a batch of simulated games came to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub won: u64,
+    pub lost: u64,
+    pub lengths: GameLengths,
+}
+
+impl Tally {
+    pub fn record(mut self, (outcome, turns): (Outcome, u32)) -> Tally {
+        match outcome {
+            Outcome::Won => self.won += 1,
+            Outcome::Lost => self.lost += 1,
+        }
+        self.lengths.record(outcome, turns);
+        self
+    }
+
+    pub fn merge(self, other: Tally) -> Tally {
+        Tally {
+            won: self.won + other.won,
+            lost: self.lost + other.lost,
+            lengths: self.lengths.merge(other.lengths),
+        }
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        self.won as f64 / (self.won + self.lost) as f64
+    }
+
+    pub fn interval(&self, confidence: f64) -> Interval {
+        Interval::wilson(self.won, self.won + self.lost, confidence)
+    }
+}
+
+/// Games played from each independent random stream. Results only depend on the seed and on
+/// which games were played, never on how rayon spreads the chunks across threads.
+pub const CHUNK_GAMES: u64 = 4096;
+
+/// The random stream for one chunk of games: ChaCha supports 2^64 independent streams per key,
+/// so every chunk gets its own stream under the run's seed.
+pub(crate) fn chunk_rng(seed: u64, chunk: u64) -> ChaCha12Rng {
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+    rng.set_stream(chunk);
+    rng
+}
+
+/// Plays the games numbered `games`, reporting to `progress` (wins included) as each chunk
+/// finishes.
+///
+/// `games` must start on a chunk boundary, so that every game is always played from the same
+/// point in the same stream.
+pub fn play_games(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+) -> Tally {
+    chunks(
+        games,
+        seed,
+        progress,
+        Tally::default,
+        |tally: Tally, rng| tally.record(Game::full_game(rules, strategy, rng)),
+        Tally::merge,
+        |tally| Some(tally.won),
+    )
+}
+
+/// Plays the games numbered `games` like `play_games`, but with `play` playing each game (and
+/// folding its result into a running total), and `merge` combining the totals of each chunk.
+pub fn play_chunks<T: Default + Send>(
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+    play: impl Fn(T, &mut ChaCha12Rng) -> T + Sync,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+) -> T {
+    chunks(games, seed, progress, T::default, play, merge, |_| None)
+}
+
+/// `play_chunks`, but with every total starting out as `empty()` rather than the default, for
+/// totals that need to know their size before the first game (one entry per player, say).
+pub fn play_chunks_from<T: Send>(
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+    empty: impl Fn() -> T + Sync + Send,
+    play: impl Fn(T, &mut ChaCha12Rng) -> T + Sync,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+) -> T {
+    chunks(games, seed, progress, empty, play, merge, |_| None)
+}
+
+/// `play_chunks_from`, with `won` saying how many of a chunk's games were won, if it knows.
+fn chunks<T: Send>(
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+    empty: impl Fn() -> T + Sync + Send,
+    play: impl Fn(T, &mut ChaCha12Rng) -> T + Sync,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+    won: impl Fn(&T) -> Option<u64> + Sync,
+) -> T {
+    debug_assert_eq!(games.start % CHUNK_GAMES, 0);
+    (games.start / CHUNK_GAMES..games.end.div_ceil(CHUNK_GAMES))
+        .into_par_iter()
+        .map(|chunk| {
+            let mut rng = chunk_rng(seed, chunk);
+            let first = chunk * CHUNK_GAMES;
+            let last = (first + CHUNK_GAMES).min(games.end);
+            let total = (first..last).fold(empty(), |total, _| play(total, &mut rng));
+            progress.played(last - first, won(&total));
+            total
+        })
+        .reduce(&empty, merge)
+}
+
+pub fn estimate_win_rate(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    n: u64,
+    seed: u64,
+    progress: &dyn Progress,
+) -> Tally {
+    progress.set_length(n);
+    let tally = play_games(rules, strategy, 0..n, seed, progress);
+    progress.finish();
+    tally
+}
+
+/// Keeps simulating, a parallel batch at a time, until the win rate's confidence interval is no
+/// wider than `±half_width` (or `max_games` have been played).
+pub fn estimate_win_rate_to(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    half_width: f64,
+    confidence: f64,
+    max_games: u64,
+    seed: u64,
+    progress: &dyn Progress,
+) -> Tally {
+    const MIN_BATCH: u64 = 32 * CHUNK_GAMES;
+
+    // Until we have a first estimate, plan for the worst case of a coin flip.
+    let planned = trials_for_half_width(0.5, half_width, confidence).min(max_games);
+    progress.set_length(planned);
+    let mut tally = Tally::default();
+    let mut played = 0;
+    let mut batch = MIN_BATCH.min(max_games);
+    while batch > 0 {
+        let games = played..played + batch;
+        tally = tally.merge(play_games(rules, strategy, games, seed, progress));
+        played += batch;
+
+        if tally.interval(confidence).half_width() <= half_width {
+            break;
+        }
+        // Plan the next batch to (just about) finish the job, based on what we know so far.
+        // Batches stay whole chunks so that a seeded run always plays the same games.
+        // A precision that can't be met needs "infinitely" many games, i.e. `u64::MAX`, which
+        // can't be rounded up; play all `max_games` instead.
+        let needed = trials_for_half_width(tally.win_rate(), half_width, confidence);
+        let target = needed
+            .max(played.saturating_add(MIN_BATCH))
+            .checked_next_multiple_of(CHUNK_GAMES)
+            .unwrap_or(max_games)
+            .min(max_games);
+        progress.set_length(target.max(played));
+        batch = target.saturating_sub(played);
+    }
+    progress.finish();
+    tally
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::{Callback, Silent, Snapshot};
+
+    fn play_on_threads(threads: usize, games: Range<u64>, seed: u64) -> Tally {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let rules = Rules::default();
+        pool.install(|| play_games(&rules, &crate::strategy::Random, games, seed, &Silent))
+    }
+
+    #[test]
+    fn seeded_runs_ignore_thread_count() {
+        let n = 10 * CHUNK_GAMES + 123;
+        let one = play_on_threads(1, 0..n, 7);
+        assert_eq!(one, play_on_threads(4, 0..n, 7));
+        assert_eq!(one, play_on_threads(3, 0..n, 7));
+        assert_ne!(one, play_on_threads(4, 0..n, 8));
+    }
+
+    #[test]
+    fn progress_counts_every_game_and_win() {
+        let reported = std::sync::Mutex::new((0, 0));
+        let progress = Callback::new(|snapshot: &Snapshot| {
+            *reported.lock().unwrap() = (snapshot.played, snapshot.won);
+        });
+        let n = 3 * CHUNK_GAMES + 5;
+        let tally = estimate_win_rate(&Rules::default(), &crate::strategy::Random, n, 2, &progress);
+        assert_eq!(reported.into_inner().unwrap(), (n, tally.won));
+    }
+
+    #[test]
+    fn stops_once_precise_enough() {
+        let rules = Rules::default();
+        let (half_width, confidence, max_games) = (0.002, 0.95, 100_000_000);
+        let tally = estimate_win_rate_to(
+            &rules,
+            &crate::strategy::LargestFirst,
+            half_width,
+            confidence,
+            max_games,
+            6,
+            &Silent,
+        );
+        assert!(tally.interval(confidence).half_width() <= half_width);
+        // About 170,000 games should do, for a win rate near 77%.
+        let played = tally.won + tally.lost;
+        assert!(played < max_games / 100, "{played}");
+    }
+
+    #[test]
+    fn unreachable_precision_plays_every_game() {
+        let rules = Rules::default();
+        let n = 40 * CHUNK_GAMES + 1;
+        let tally =
+            estimate_win_rate_to(&rules, &crate::strategy::Random, 0.0, 0.95, n, 4, &Silent);
+        assert_eq!(tally.won + tally.lost, n);
+    }
+
+    #[test]
+    fn seeded_runs_ignore_batching() {
+        let n = 6 * CHUNK_GAMES;
+        let split = 2 * CHUNK_GAMES;
+        let whole = play_on_threads(2, 0..n, 11);
+        let batched = play_on_threads(2, 0..split, 11).merge(play_on_threads(2, split..n, 11));
+        assert_eq!(whole, batched);
+    }
+}
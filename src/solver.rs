@@ -0,0 +1,402 @@
+//! Exact win probabilities, found by memoized recursion over every reachable `Game` state.
+//!
+//! Every roll either moves the game strictly "forward" (fewer apples, or the bird closer) or leaves
+//! it untouched (a color whose orchard is already empty), so the only cycles in the state graph are
+//! self-loops. Those can be folded away analytically: if `k` of the die's `n` faces leave a state
+//! unchanged, the game simply re-rolls until it gets one of the other `n - k` faces (weighting
+//! each face by how likely it is, for a die that isn't fair).
+//!
+//! With a strategy (and a die) that treats every color alike, states are only ever solved in their
+//! canonical form (see `Game::canonical`), which cuts the states to visit by up to `colors!`.
+
+use std::collections::HashMap;
+
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::rules::Rules;
+use crate::strategy::BasketStrategy;
+use crate::{DieRoll, Game, Outcome};
+
+/// Exact win probability and expected game length for a single set of rules.
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub win_probability: BigRational,
+    /// Expected number of rolls until the game is over, won or lost.
+    pub expected_turns: BigRational,
+}
+
+impl Solution {
+    pub fn win_rate(&self) -> f64 {
+        // Unwrap: a probability always fits in an f64 (though it may be rounded).
+        self.win_probability.to_f64().unwrap()
+    }
+
+    pub fn mean_turns(&self) -> f64 {
+        // Unwrap: as above; games are never so long that their length overflows.
+        self.expected_turns.to_f64().unwrap()
+    }
+}
+
+/// Everything the solver works out about a state, so both can share one pass over the states.
+#[derive(Debug, Clone)]
+struct Value {
+    win_probability: BigRational,
+    expected_turns: BigRational,
+}
+
+impl Value {
+    fn finished(outcome: Outcome) -> Value {
+        let win_probability = match outcome {
+            Outcome::Won => BigRational::one(),
+            Outcome::Lost => BigRational::zero(),
+        };
+        Value {
+            win_probability,
+            expected_turns: BigRational::zero(),
+        }
+    }
+
+    fn zero() -> Value {
+        Value::finished(Outcome::Lost)
+    }
+
+    fn add(&mut self, other: &Value) {
+        self.win_probability += &other.win_probability;
+        self.expected_turns += &other.expected_turns;
+    }
+
+    fn add_weighted(&mut self, other: &Value, weight: u32) {
+        if weight == 1 {
+            return self.add(other);
+        }
+        let weight = BigRational::from_integer(weight.into());
+        self.win_probability += &other.win_probability * &weight;
+        self.expected_turns += &other.expected_turns * weight;
+    }
+
+    fn average(self, count: usize) -> Value {
+        let count = BigRational::from_integer(count.into());
+        Value {
+            win_probability: self.win_probability / &count,
+            expected_turns: self.expected_turns / count,
+        }
+    }
+}
+
+/// Memoizes state values so that repeated queries share work.
+pub struct Solver<'a> {
+    rules: &'a Rules,
+    strategy: &'a dyn BasketStrategy,
+    /// Whether states can be solved in their canonical form.
+    symmetric: bool,
+    memo: HashMap<Game, Value>,
+}
+
+impl<'a> Solver<'a> {
+    pub fn new(rules: &'a Rules, strategy: &'a dyn BasketStrategy) -> Solver<'a> {
+        Solver {
+            rules,
+            strategy,
+            symmetric: strategy.is_symmetric() && rules.colors_alike(),
+            memo: HashMap::new(),
+        }
+    }
+
+    /// Solves the game from the start the rules describe.
+    pub fn solve(&mut self) -> Solution {
+        let start = Game::new(self.rules);
+        Solution {
+            win_probability: self.win_probability(start),
+            expected_turns: self.expected_turns(start),
+        }
+    }
+
+    /// Probability of winning from `game`, assuming it has not already finished.
+    pub fn win_probability(&mut self, game: Game) -> BigRational {
+        self.state_value(game).win_probability
+    }
+
+    /// Expected number of rolls left in `game`, assuming it has not already finished.
+    pub fn expected_turns(&mut self, game: Game) -> BigRational {
+        self.state_value(game).expected_turns
+    }
+
+    fn state_value(&mut self, game: Game) -> Value {
+        let game = if self.symmetric {
+            game.canonical()
+        } else {
+            game
+        };
+        if let Some(value) = self.memo.get(&game) {
+            return value.clone();
+        }
+
+        let mut total = Value::zero();
+        let mut moving_weight = 0;
+        for (roll, weight) in self.rules.die() {
+            let mut next = game;
+            let value = match roll {
+                DieRoll::Basket => self.basket_value(game, self.rules.basket_picks),
+                DieRoll::Bird => {
+                    let outcome = next.move_bird();
+                    self.value(next, outcome)
+                }
+                DieRoll::Fruit(orchard) => {
+                    let outcome = next.pick(orchard);
+                    if next == game {
+                        continue;
+                    }
+                    self.value(next, outcome)
+                }
+            };
+            total.add_weighted(&value, weight);
+            moving_weight += weight;
+        }
+
+        // Every roll takes a turn, including the ones that re-roll: on average it takes
+        // `total_weight / moving_weight` of them to get anywhere.
+        total.expected_turns += BigRational::from_integer(self.rules.total_weight().into());
+        let value = total.average(moving_weight as usize);
+        self.memo.insert(game, value.clone());
+        value
+    }
+
+    /// Value of `game` with `picks_left` fruit still to take from the basket.
+    fn basket_value(&mut self, game: Game, picks_left: u8) -> Value {
+        // The strategy may pick at random; average over everything it might do.
+        let choices = self.strategy.choices(&game, picks_left);
+        let mut total = Value::zero();
+        for &orchard in &choices {
+            let mut next = game;
+            let outcome = next.pick(orchard);
+            let value = match outcome {
+                None if picks_left > 1 => self.basket_value(next, picks_left - 1),
+                _ => self.value(next, outcome),
+            };
+            total.add(&value);
+        }
+        total.average(choices.len())
+    }
+
+    /// Value of having just arrived at `game`, which may have ended it.
+    fn value(&mut self, game: Game, outcome: Option<Outcome>) -> Value {
+        match outcome {
+            Some(outcome) => Value::finished(outcome),
+            None => self.state_value(game),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::{ColorOrder, Favourite, LargestFirst, Random, SmallestFirst};
+    use num_bigint::BigInt;
+    use rand::prelude::*;
+
+    #[test]
+    fn single_apple_left() {
+        // Red or basket wins, bird loses, anything else re-rolls.
+        let game = Game {
+            bird_position: 1,
+            orchards: [1, 0, 0, 0].into(),
+        };
+        let expected = BigRational::new(BigInt::from(2), BigInt::from(3));
+        let rules = Rules::default();
+        assert_eq!(
+            Solver::new(&rules, &LargestFirst).win_probability(game),
+            expected
+        );
+    }
+
+    #[test]
+    fn basket_picks_two() {
+        // Basket wins outright; red or green leave one apple, from which we win 2/3 of the time.
+        let game = Game {
+            bird_position: 1,
+            orchards: [1, 1, 0, 0].into(),
+        };
+        let expected = BigRational::new(BigInt::from(7), BigInt::from(12));
+        let rules = Rules {
+            basket_picks: 2,
+            ..Rules::default()
+        };
+        assert_eq!(
+            Solver::new(&rules, &LargestFirst).win_probability(game),
+            expected
+        );
+    }
+
+    #[test]
+    fn expected_turns_with_one_apple_left() {
+        // Each roll finishes the game with probability 1/2, so it takes two on average.
+        let game = Game {
+            bird_position: 1,
+            orchards: [1, 0, 0, 0].into(),
+        };
+        let rules = Rules::default();
+        assert_eq!(
+            Solver::new(&rules, &LargestFirst).expected_turns(game),
+            BigRational::from_integer(2.into())
+        );
+    }
+
+    #[test]
+    fn harder_starts_are_harder() {
+        let rates: Vec<f64> = (1..=8)
+            .map(|track_length| {
+                let rules = Rules {
+                    track_length,
+                    ..Rules::default()
+                };
+                Solver::new(&rules, &LargestFirst).solve().win_rate()
+            })
+            .collect();
+        assert!(rates.windows(2).all(|w| w[0] < w[1]), "{rates:?}");
+    }
+
+    #[test]
+    fn simulation_matches_exact() {
+        let strategies: [&dyn BasketStrategy; 5] = [
+            &LargestFirst,
+            &SmallestFirst,
+            &Random,
+            &ColorOrder(vec![3, 2, 1, 0]),
+            &Favourite(2),
+        ];
+        let house_rules = Rules {
+            colors: 3,
+            apples: 5,
+            bird_faces: 2,
+            basket_faces: 0,
+            basket_picks: 1,
+            track_length: 6,
+            face_weights: vec![],
+        };
+        let small_orchard = Rules {
+            apples: 4,
+            track_length: 5,
+            ..Rules::orchard()
+        };
+        // A die worn in favour of red and the basket.
+        let loaded = Rules {
+            face_weights: vec![3, 1, 1, 1, 2, 1],
+            ..Rules::default()
+        };
+        let mut cases: Vec<(Rules, &dyn BasketStrategy)> = vec![
+            (house_rules, &Random),
+            (small_orchard, &SmallestFirst),
+            (loaded, &LargestFirst),
+        ];
+        for strategy in strategies {
+            for track_length in 4..=6 {
+                let rules = Rules {
+                    track_length,
+                    ..Rules::default()
+                };
+                cases.push((rules, strategy));
+            }
+        }
+
+        let n = 50_000;
+        let mut rng = StdRng::seed_from_u64(0x0c4a4d);
+        for (rules, strategy) in cases {
+            let exact = Solver::new(&rules, strategy).solve().win_rate();
+            let mut won = 0;
+            let mut turns = Vec::with_capacity(n);
+            for _ in 0..n {
+                let (outcome, length) = Game::full_game(&rules, strategy, &mut rng);
+                won += usize::from(outcome == Outcome::Won);
+                turns.push(f64::from(length));
+            }
+
+            let estimate = won as f64 / n as f64;
+            let sigma = (exact * (1.0 - exact) / n as f64).sqrt();
+            assert!(
+                (estimate - exact).abs() < 4.0 * sigma,
+                "{rules:?}: simulated {estimate}, exact {exact}"
+            );
+
+            let expected = Solver::new(&rules, strategy).solve().mean_turns();
+            let mean = turns.iter().sum::<f64>() / n as f64;
+            let variance = turns.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / n as f64;
+            assert!(
+                (mean - expected).abs() < 4.0 * (variance / n as f64).sqrt(),
+                "{rules:?}: simulated mean length {mean}, exact {expected}"
+            );
+        }
+    }
+
+    /// Passes choices through from another strategy, without claiming to treat colors alike.
+    struct Labelled<'a>(&'a dyn BasketStrategy);
+
+    impl BasketStrategy for Labelled<'_> {
+        fn choose(&self, game: &Game, picks_left: u8, rng: &mut dyn RngCore) -> usize {
+            self.0.choose(game, picks_left, rng)
+        }
+
+        fn choices(&self, game: &Game, picks_left: u8) -> Vec<usize> {
+            self.0.choices(game, picks_left)
+        }
+    }
+
+    #[test]
+    fn canonical_states_have_the_same_successors() {
+        let rules = Rules {
+            apples: 2,
+            basket_picks: 2,
+            track_length: 2,
+            ..Rules::default()
+        };
+        let successors = |game: Game| {
+            let mut successors: Vec<_> = rules
+                .die()
+                .map(|(roll, _)| {
+                    let mut next = game;
+                    let outcome = next.apply(&rules, roll, &LargestFirst, &mut thread_rng());
+                    (
+                        next.canonical().orchards.to_vec(),
+                        next.bird_position,
+                        outcome,
+                    )
+                })
+                .collect();
+            successors.sort();
+            successors
+        };
+        for bird_position in 1..=2 {
+            for apples in 0..3u32.pow(4) {
+                let orchards = [0, 1, 2, 3].map(|color| (apples / 3u32.pow(color) % 3) as u8);
+                let game = Game {
+                    bird_position,
+                    orchards: orchards.into(),
+                };
+                if game.outcome().is_some() {
+                    continue;
+                }
+                assert_eq!(successors(game), successors(game.canonical()), "{game:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_states_have_the_same_values() {
+        let rules = Rules {
+            apples: 3,
+            track_length: 4,
+            ..Rules::orchard()
+        };
+        for strategy in [
+            &LargestFirst as &dyn BasketStrategy,
+            &SmallestFirst,
+            &Random,
+        ] {
+            let canonical = Solver::new(&rules, strategy).solve();
+            let labelled = Labelled(strategy);
+            let every_state = Solver::new(&rules, &labelled).solve();
+            assert_eq!(canonical.win_probability, every_state.win_probability);
+            assert_eq!(canonical.expected_turns, every_state.expected_turns);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Summaries of many simulated games: how long they take, and how sure we can be of the win rate.
+
+use crate::Outcome;
+
+/// Number of games that took each number of turns, kept separately for wins and losses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameLengths {
+    won: Vec<u64>,
+    lost: Vec<u64>,
+}
+
+impl GameLengths {
+    pub fn record(&mut self, outcome: Outcome, turns: u32) {
+        let counts = match outcome {
+            Outcome::Won => &mut self.won,
+            Outcome::Lost => &mut self.lost,
+        };
+        let turns = turns as usize;
+        if counts.len() <= turns {
+            counts.resize(turns + 1, 0);
+        }
+        counts[turns] += 1;
+    }
+
+    pub fn merge(mut self, other: GameLengths) -> GameLengths {
+        for (counts, other) in [(&mut self.won, other.won), (&mut self.lost, other.lost)] {
+            if counts.len() < other.len() {
+                counts.resize(other.len(), 0);
+            }
+            for (count, other) in counts.iter_mut().zip(other) {
+                *count += other;
+            }
+        }
+        self
+    }
+
+    /// Number of games that took each number of turns, for games ending in `outcome` (or all
+    /// games, if that's `None`).
+    pub fn histogram(&self, outcome: Option<Outcome>) -> Vec<u64> {
+        match outcome {
+            Some(Outcome::Won) => self.won.clone(),
+            Some(Outcome::Lost) => self.lost.clone(),
+            None => self.clone().combined(),
+        }
+    }
+
+    fn combined(self) -> Vec<u64> {
+        let (mut longer, shorter) = if self.won.len() >= self.lost.len() {
+            (self.won, self.lost)
+        } else {
+            (self.lost, self.won)
+        };
+        for (count, other) in longer.iter_mut().zip(shorter) {
+            *count += other;
+        }
+        longer
+    }
+
+    pub fn games(&self, outcome: Option<Outcome>) -> u64 {
+        self.histogram(outcome).iter().sum()
+    }
+
+    /// Average number of turns, if there were any such games.
+    pub fn mean(&self, outcome: Option<Outcome>) -> Option<f64> {
+        let histogram = self.histogram(outcome);
+        let games: u64 = histogram.iter().sum();
+        let total: u64 = histogram
+            .iter()
+            .enumerate()
+            .map(|(turns, &count)| turns as u64 * count)
+            .sum();
+        (games > 0).then(|| total as f64 / games as f64)
+    }
+
+    /// The smallest number of turns that at least a fraction `p` of games took no more than.
+    pub fn percentile(&self, outcome: Option<Outcome>, p: f64) -> Option<u32> {
+        let histogram = self.histogram(outcome);
+        let games: u64 = histogram.iter().sum();
+        let target = (p * games as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (turns, &count) in histogram.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some(turns as u32);
+            }
+        }
+        None
+    }
+
+    pub fn median(&self, outcome: Option<Outcome>) -> Option<u32> {
+        self.percentile(outcome, 0.5)
+    }
+}
+
+/// A confidence interval for a proportion, such as a win rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Interval {
+    /// The Wilson score interval for `successes` out of `trials`, which (unlike the textbook
+    /// normal approximation) behaves itself near 0 and 1 and never leaves [0, 1].
+    pub fn wilson(successes: u64, trials: u64, confidence: f64) -> Interval {
+        if trials == 0 {
+            return Interval {
+                low: 0.0,
+                high: 1.0,
+            };
+        }
+        let n = trials as f64;
+        let p = successes as f64 / n;
+        let z = normal_quantile(0.5 + confidence / 2.0);
+        let z2 = z * z;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half_width = z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+        Interval {
+            low: (center - half_width).max(0.0),
+            high: (center + half_width).min(1.0),
+        }
+    }
+
+    /// The normal approximation interval around an estimate with the given standard error, for
+    /// estimators that aren't a plain count of successes.
+    pub fn normal(estimate: f64, standard_error: f64, confidence: f64) -> Interval {
+        let half_width = normal_quantile(0.5 + confidence / 2.0) * standard_error;
+        Interval {
+            low: estimate - half_width,
+            high: estimate + half_width,
+        }
+    }
+
+    pub fn half_width(&self) -> f64 {
+        (self.high - self.low) / 2.0
+    }
+}
+
+/// Roughly how many trials it takes for a proportion near `p` to be pinned down to within
+/// `half_width`, at the given confidence.
+pub fn trials_for_half_width(p: f64, half_width: f64, confidence: f64) -> u64 {
+    let z = normal_quantile(0.5 + confidence / 2.0);
+    (z * z * p * (1.0 - p) / (half_width * half_width)).ceil() as u64
+}
+
+/// The inverse of the standard normal CDF, using Acklam's rational approximation (relative error
+/// below 1.2e-9, which is plenty for confidence intervals).
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    const LOW: f64 = 0.02425;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// The chance of a chi-square statistic with `degrees_of_freedom` being at least `statistic`,
+/// i.e. the p-value of a chi-square (or G) test that came out at `statistic`.
+pub fn chi_square_p_value(statistic: f64, degrees_of_freedom: u32) -> f64 {
+    if statistic <= 0.0 {
+        return 1.0;
+    }
+    upper_regularized_gamma(f64::from(degrees_of_freedom) / 2.0, statistic / 2.0)
+}
+
+/// Q(a, x) = Γ(a, x) / Γ(a), by its series for small `x` and by Lentz's continued fraction
+/// otherwise (Numerical Recipes, section 6.2), to about 1e-14.
+fn upper_regularized_gamma(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+    let prefactor = (a * x.ln() - x - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..1000 {
+            term *= x / (a + f64::from(n));
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        1.0 - sum * prefactor
+    } else {
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut fraction = d;
+        for n in 1..1000 {
+            let n = f64::from(n);
+            let an = -n * (n - a);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < TINY { TINY } else { d };
+            c = b + an / c;
+            c = if c.abs() < TINY { TINY } else { c };
+            d = 1.0 / d;
+            let delta = d * c;
+            fraction *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        fraction * prefactor
+    }
+}
+
+/// ln Γ(x) for x > 0, by the Lanczos approximation (g = 7, nine coefficients).
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // The reflection formula, since the approximation is for x >= 1/2.
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let series = COEFFICIENTS[1..]
+        .iter()
+        .zip(1..)
+        .fold(COEFFICIENTS[0], |sum, (c, i)| sum + c / (x + f64::from(i)));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_statistics() {
+        let mut a = GameLengths::default();
+        for turns in [10, 12, 12, 20] {
+            a.record(Outcome::Won, turns);
+        }
+        let mut b = GameLengths::default();
+        b.record(Outcome::Lost, 6);
+        let lengths = a.merge(b);
+
+        assert_eq!(lengths.games(None), 5);
+        assert_eq!(lengths.games(Some(Outcome::Lost)), 1);
+        assert_eq!(lengths.mean(Some(Outcome::Won)), Some(13.5));
+        assert_eq!(lengths.mean(None), Some(12.0));
+        assert_eq!(lengths.median(None), Some(12));
+        assert_eq!(lengths.percentile(None, 0.0), Some(6));
+        assert_eq!(lengths.percentile(Some(Outcome::Won), 1.0), Some(20));
+        assert_eq!(GameLengths::default().median(None), None);
+    }
+
+    #[test]
+    fn normal_quantiles() {
+        assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+        assert!((normal_quantile(0.5)).abs() < 1e-12);
+        assert!((normal_quantile(0.001) + 3.090232).abs() < 1e-6);
+    }
+
+    #[test]
+    fn wilson_interval() {
+        // 81 out of 263, worked through Wilson's formula by hand.
+        let interval = Interval::wilson(81, 263, 0.95);
+        assert!((interval.low - 0.2553).abs() < 1e-4, "{interval:?}");
+        assert!((interval.high - 0.3662).abs() < 1e-4, "{interval:?}");
+
+        let interval = Interval::wilson(0, 10, 0.95);
+        assert_eq!(interval.low, 0.0);
+        assert!(interval.high > 0.0);
+    }
+
+    #[test]
+    fn chi_square_p_values() {
+        // With two degrees of freedom the tail is exactly exp(-x / 2).
+        for x in [0.1, 1.0, 5.0, 30.0] {
+            assert!((chi_square_p_value(x, 2) - (-x / 2.0).exp()).abs() < 1e-12);
+        }
+        // The usual 5% critical values.
+        assert!((chi_square_p_value(3.841459, 1) - 0.05).abs() < 1e-6);
+        assert!((chi_square_p_value(11.070498, 5) - 0.05).abs() < 1e-6);
+        assert!((chi_square_p_value(0.554300, 5) - 0.99).abs() < 1e-6);
+        assert_eq!(chi_square_p_value(0.0, 3), 1.0);
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-12);
+        assert!((ln_gamma(0.5) - std::f64::consts::PI.sqrt().ln()).abs() < 1e-12);
+    }
+}
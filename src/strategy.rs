@@ -0,0 +1,195 @@
+//! Ways of deciding which orchard to pick from when the basket is rolled.
+
+use rand::prelude::*;
+
+use crate::Game;
+
+/// Chooses the orchard to take an apple from when the basket comes up.
+///
+/// Strategies are only consulted while at least one orchard still has apples, and must always
+/// return an orchard that does.
+///
+/// When a basket lets the players take several fruit, the strategy is asked once per fruit, with
+/// `picks_left` counting down to 1 for the last of them.
+pub trait BasketStrategy: Sync {
+    /// Picks an orchard for a single fruit.
+    fn choose(&self, game: &Game, picks_left: u8, rng: &mut dyn RngCore) -> usize;
+
+    /// Every orchard `choose` might return for `game`, each equally likely.
+    ///
+    /// The exact solver uses this to average over a strategy's randomness instead of sampling it.
+    fn choices(&self, game: &Game, picks_left: u8) -> Vec<usize>;
+
+    /// Whether the strategy treats every color alike: with the colors relabelled, it picks (or
+    /// might pick) from orchards with just as many apples left as before.
+    ///
+    /// The solvers only merge states that differ by a relabelling (see [`Game::canonical`]) for
+    /// strategies that do.
+    fn is_symmetric(&self) -> bool {
+        false
+    }
+}
+
+fn non_empty(game: &Game) -> impl Iterator<Item = usize> + '_ {
+    (0..game.orchards.len()).filter(|&i| game.orchards[i] > 0)
+}
+
+/// Takes from whichever orchard has the most apples left, breaking ties towards the last color.
+#[derive(Debug, Clone, Copy)]
+pub struct LargestFirst;
+
+impl LargestFirst {
+    fn pick(game: &Game) -> usize {
+        // Unwrap: strategies are only asked while some orchard has apples.
+        non_empty(game).max_by_key(|&i| game.orchards[i]).unwrap()
+    }
+}
+
+impl BasketStrategy for LargestFirst {
+    fn choose(&self, game: &Game, _picks_left: u8, _rng: &mut dyn RngCore) -> usize {
+        LargestFirst::pick(game)
+    }
+
+    fn choices(&self, game: &Game, _picks_left: u8) -> Vec<usize> {
+        vec![LargestFirst::pick(game)]
+    }
+
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// Takes from whichever orchard has the fewest (but not zero) apples left, breaking ties towards
+/// the first color.
+#[derive(Debug, Clone, Copy)]
+pub struct SmallestFirst;
+
+impl SmallestFirst {
+    fn pick(game: &Game) -> usize {
+        // Unwrap: strategies are only asked while some orchard has apples.
+        non_empty(game).min_by_key(|&i| game.orchards[i]).unwrap()
+    }
+}
+
+impl BasketStrategy for SmallestFirst {
+    fn choose(&self, game: &Game, _picks_left: u8, _rng: &mut dyn RngCore) -> usize {
+        SmallestFirst::pick(game)
+    }
+
+    fn choices(&self, game: &Game, _picks_left: u8) -> Vec<usize> {
+        vec![SmallestFirst::pick(game)]
+    }
+
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// Takes from any orchard with apples left, uniformly at random.
+#[derive(Debug, Clone, Copy)]
+pub struct Random;
+
+impl BasketStrategy for Random {
+    fn choose(&self, game: &Game, _picks_left: u8, rng: &mut dyn RngCore) -> usize {
+        // Unwrap: strategies are only asked while some orchard has apples.
+        non_empty(game).choose(rng).unwrap()
+    }
+
+    fn choices(&self, game: &Game, _picks_left: u8) -> Vec<usize> {
+        non_empty(game).collect()
+    }
+
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// Works through the colors in a fixed order, emptying each orchard before moving to the next.
+///
+/// Colors missing from the order are visited last, in their natural order.
+#[derive(Debug, Clone)]
+pub struct ColorOrder(pub Vec<usize>);
+
+impl ColorOrder {
+    fn pick(&self, game: &Game) -> usize {
+        self.0
+            .iter()
+            .copied()
+            .chain(0..game.orchards.len())
+            .find(|&i| game.orchards.get(i).is_some_and(|&apples| apples > 0))
+            // Unwrap: strategies are only asked while some orchard has apples.
+            .unwrap()
+    }
+}
+
+impl BasketStrategy for ColorOrder {
+    fn choose(&self, game: &Game, _picks_left: u8, _rng: &mut dyn RngCore) -> usize {
+        self.pick(game)
+    }
+
+    fn choices(&self, game: &Game, _picks_left: u8) -> Vec<usize> {
+        vec![self.pick(game)]
+    }
+}
+
+/// The child always grabs their favourite color, and any color at all once that one is gone.
+#[derive(Debug, Clone, Copy)]
+pub struct Favourite(pub usize);
+
+impl BasketStrategy for Favourite {
+    fn choose(&self, game: &Game, _picks_left: u8, rng: &mut dyn RngCore) -> usize {
+        if game.orchards[self.0] > 0 {
+            self.0
+        } else {
+            Random.choose(game, 1, rng)
+        }
+    }
+
+    fn choices(&self, game: &Game, _picks_left: u8) -> Vec<usize> {
+        if game.orchards[self.0] > 0 {
+            vec![self.0]
+        } else {
+            Random.choices(game, 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choices_have_apples() {
+        let strategies: [&dyn BasketStrategy; 5] = [
+            &LargestFirst,
+            &SmallestFirst,
+            &Random,
+            &ColorOrder(vec![2, 0]),
+            &Favourite(1),
+        ];
+        let game = Game {
+            bird_position: 3,
+            orchards: [2, 0, 1, 3].into(),
+        };
+        let mut rng = StdRng::seed_from_u64(3);
+        for strategy in strategies {
+            let choices = strategy.choices(&game, 1);
+            assert!(!choices.is_empty());
+            assert!(choices.iter().all(|&i| game.orchards[i] > 0));
+            assert!(choices.contains(&strategy.choose(&game, 1, &mut rng)));
+        }
+    }
+
+    #[test]
+    fn built_in_choices() {
+        let game = Game {
+            bird_position: 3,
+            orchards: [3, 0, 1, 3].into(),
+        };
+        assert_eq!(LargestFirst.choices(&game, 1), [3]);
+        assert_eq!(SmallestFirst.choices(&game, 1), [2]);
+        assert_eq!(Random.choices(&game, 1), [0, 2, 3]);
+        assert_eq!(ColorOrder(vec![1, 2]).choices(&game, 1), [2]);
+        assert_eq!(Favourite(1).choices(&game, 1), [0, 2, 3]);
+    }
+}
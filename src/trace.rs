@@ -0,0 +1,515 @@
+//! Complete records of single games: every roll, every state along the way, and how it ended.
+//!
+//! Trace files are plain text, one game per line, so that they stay small and can be grepped. A
+//! file starts with an `orchard-trace 1` line; then each run of games gives its rules on a
+//! `rules` line (optionally after `#` comment lines), followed by its games. A game is its
+//! outcome (`W` or `L`), the final bird position, the final orchards separated by commas, and
+//! then the rolls with no spaces between them: a digit for a fruit color, `B` for the bird, and
+//! `K` for the basket followed by a letter for each orchard it picked (`a` for the first color,
+//! and so on). For example, `W 2 0,0,0,0 013Ka2...`.
+//!
+//! Only the last state is written out; the rest are recovered by replaying the rolls, which also
+//! checks that the trace is still a legal game under the rules.
+
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+
+use rand::rngs::mock::StepRng;
+use rand::{Rng, RngCore};
+
+use crate::rules::Rules;
+use crate::simulate::{chunk_rng, CHUNK_GAMES};
+use crate::strategy::BasketStrategy;
+use crate::{DieRoll, Game, Orchards, Outcome, MAX_COLORS};
+
+/// One roll of the die, and the orchards picked from if it was the basket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Roll {
+    pub roll: DieRoll,
+    /// Orchards the basket took fruit from, in order; empty for any other roll.
+    pub picks: Vec<usize>,
+}
+
+/// A game from start to finish.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub rolls: Vec<Roll>,
+    /// The state at the start, and after each roll; one longer than `rolls`.
+    pub states: Vec<Game>,
+    pub outcome: Outcome,
+}
+
+impl Trace {
+    pub fn turns(&self) -> u32 {
+        self.rolls.len() as u32
+    }
+
+    /// The game as a line of a trace file (without the newline).
+    pub fn encode(&self) -> String {
+        // Unwrap: there's always at least the starting state.
+        let last = self.states.last().unwrap();
+        let orchards: Vec<String> = last.orchards.iter().map(u8::to_string).collect();
+        let mut line = format!(
+            "{} {} {} ",
+            match self.outcome {
+                Outcome::Won => 'W',
+                Outcome::Lost => 'L',
+            },
+            last.bird_position,
+            orchards.join(",")
+        );
+        for roll in &self.rolls {
+            match roll.roll {
+                DieRoll::Fruit(orchard) => line.push_str(&orchard.to_string()),
+                DieRoll::Bird => line.push('B'),
+                DieRoll::Basket => line.push('K'),
+            }
+            line.extend(
+                roll.picks
+                    .iter()
+                    .map(|&orchard| char::from(b'a' + orchard as u8)),
+            );
+        }
+        line
+    }
+}
+
+/// A game as read back from a trace file, before it has been replayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recorded {
+    pub outcome: Outcome,
+    pub last: Game,
+    pub rolls: Vec<Roll>,
+}
+
+impl Recorded {
+    /// Parses a line written by `Trace::encode`.
+    pub fn decode(line: &str) -> Result<Recorded, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [outcome, bird_position, orchards, rolls] = fields[..] else {
+            return Err(format!("expected 4 fields, found {}", fields.len()));
+        };
+        let outcome = match outcome {
+            "W" => Outcome::Won,
+            "L" => Outcome::Lost,
+            _ => return Err(format!("unknown outcome '{outcome}'")),
+        };
+        let bird_position = bird_position
+            .parse()
+            .map_err(|_| format!("bad bird position '{bird_position}'"))?;
+        let apples = orchards
+            .split(',')
+            .map(|apples| apples.parse::<u8>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("bad orchards '{orchards}'"))?;
+        if apples.len() > MAX_COLORS {
+            return Err(format!(
+                "at most {MAX_COLORS} orchards, found {}",
+                apples.len()
+            ));
+        }
+        let mut last = Game {
+            bird_position,
+            orchards: Orchards::new(apples.len() as u8, 0),
+        };
+        last.orchards.copy_from_slice(&apples);
+
+        let mut parsed: Vec<Roll> = Vec::new();
+        for c in rolls.chars() {
+            let roll = match c {
+                '0'..='9' => DieRoll::Fruit(c as usize - '0' as usize),
+                'B' => DieRoll::Bird,
+                'K' => DieRoll::Basket,
+                'a'..='z' => match parsed.last_mut() {
+                    Some(last) if last.roll == DieRoll::Basket => {
+                        last.picks.push(c as usize - 'a' as usize);
+                        continue;
+                    }
+                    _ => return Err(format!("basket pick '{c}' without a basket")),
+                },
+                _ => return Err(format!("unknown roll '{c}'")),
+            };
+            parsed.push(Roll {
+                roll,
+                picks: Vec::new(),
+            });
+        }
+        Ok(Recorded {
+            outcome,
+            last,
+            rolls: parsed,
+        })
+    }
+
+    /// Plays the rolls again under `rules`, checking that each is legal, that the game ends on
+    /// the last of them, and that it ends the way (and in the state) the trace says it did.
+    pub fn replay(&self, rules: &Rules) -> Result<Trace, String> {
+        let mut game = Game::new(rules);
+        let mut states = vec![game];
+        for (turn, roll) in (1..).zip(&self.rolls) {
+            let error = |message: String| format!("roll {turn}: {message}");
+            check_roll(rules, &game, roll).map_err(error)?;
+            let script = Scripted(Mutex::new(roll.picks.clone()));
+            let outcome = game.apply(rules, roll.roll, &script, &mut StepRng::new(0, 0));
+            states.push(game);
+            let Some(outcome) = outcome else {
+                continue;
+            };
+
+            if turn != self.rolls.len() {
+                return Err(error("the game is over, but the trace goes on".to_owned()));
+            }
+            if outcome != self.outcome {
+                return Err(format!("ended {outcome:?}, not {:?}", self.outcome));
+            }
+            if game != self.last {
+                return Err(format!("ended as {game:?}, not {:?}", self.last));
+            }
+            return Ok(Trace {
+                rolls: self.rolls.clone(),
+                states,
+                outcome,
+            });
+        }
+        Err("the trace ends before the game does".to_owned())
+    }
+}
+
+/// Checks that `roll` is one the die can show under `rules`, and that any basket picks are ones
+/// the players could make in `game`.
+fn check_roll(rules: &Rules, game: &Game, roll: &Roll) -> Result<(), String> {
+    match roll.roll {
+        DieRoll::Fruit(orchard) if orchard >= usize::from(rules.colors) => {
+            return Err(format!("there is no orchard {orchard}"));
+        }
+        DieRoll::Basket if rules.basket_faces == 0 => {
+            return Err("the die has no basket".to_owned());
+        }
+        DieRoll::Bird if rules.bird_faces == 0 => return Err("the die has no bird".to_owned()),
+        DieRoll::Basket => {}
+        _ if !roll.picks.is_empty() => return Err("only the basket picks".to_owned()),
+        _ => return Ok(()),
+    }
+
+    let mut game = *game;
+    for (pick, &orchard) in (1..).zip(&roll.picks) {
+        if game.orchards.get(orchard).copied().unwrap_or_default() == 0 {
+            return Err(format!(
+                "the basket can't pick from empty orchard {orchard}"
+            ));
+        }
+        if game.pick(orchard).is_some() && pick != roll.picks.len() {
+            return Err("the basket picks on after the game is won".to_owned());
+        }
+    }
+    if game.outcome().is_none() && roll.picks.len() != usize::from(rules.basket_picks) {
+        return Err(format!(
+            "the basket picks {}, not {}",
+            rules.basket_picks,
+            roll.picks.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Replays recorded basket picks, in order.
+struct Scripted(Mutex<Vec<usize>>);
+
+impl BasketStrategy for Scripted {
+    fn choose(&self, _game: &Game, _picks_left: u8, _rng: &mut dyn RngCore) -> usize {
+        // Unwrap: `check_roll` made sure there are enough picks.
+        self.0.lock().unwrap().remove(0)
+    }
+
+    fn choices(&self, _game: &Game, _picks_left: u8) -> Vec<usize> {
+        // Unwrap: as above.
+        vec![self.0.lock().unwrap()[0]]
+    }
+}
+
+/// Passes choices through from another strategy, remembering them.
+struct Recording<'a, S: ?Sized> {
+    strategy: &'a S,
+    picks: Mutex<Vec<usize>>,
+}
+
+impl<S: BasketStrategy + ?Sized> BasketStrategy for Recording<'_, S> {
+    fn choose(&self, game: &Game, picks_left: u8, rng: &mut dyn RngCore) -> usize {
+        let orchard = self.strategy.choose(game, picks_left, rng);
+        // Unwrap: nothing panics while holding the lock.
+        self.picks.lock().unwrap().push(orchard);
+        orchard
+    }
+
+    fn choices(&self, game: &Game, picks_left: u8) -> Vec<usize> {
+        self.strategy.choices(game, picks_left)
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.strategy.is_symmetric()
+    }
+}
+
+impl Game {
+    /// Plays a game to the end like `full_game`, drawing exactly the same random numbers, but
+    /// keeps a full record of it.
+    pub fn traced_game(
+        rules: &Rules,
+        strategy: &(impl BasketStrategy + ?Sized),
+        rng: &mut impl Rng,
+    ) -> Trace {
+        let mut game = Game::new(rules);
+        let mut states = vec![game];
+        let mut rolls = Vec::new();
+        loop {
+            let roll = rng.sample(rules);
+            let recording = Recording {
+                strategy,
+                picks: Mutex::new(Vec::new()),
+            };
+            let outcome = game.apply(rules, roll, &recording, rng);
+            rolls.push(Roll {
+                roll,
+                // Unwrap: as in `Recording::choose`.
+                picks: recording.picks.into_inner().unwrap(),
+            });
+            states.push(game);
+            if let Some(outcome) = outcome {
+                return Trace {
+                    rolls,
+                    states,
+                    outcome,
+                };
+            }
+        }
+    }
+}
+
+/// Traces the first `games` games of a seeded run: the very same games `simulate::play_games`
+/// plays with that seed.
+pub fn trace_games(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    games: u64,
+    seed: u64,
+) -> Vec<Trace> {
+    let mut traces = Vec::new();
+    for chunk in 0..games.div_ceil(CHUNK_GAMES) {
+        let mut rng = chunk_rng(seed, chunk);
+        for _ in chunk * CHUNK_GAMES..games.min((chunk + 1) * CHUNK_GAMES) {
+            traces.push(Game::traced_game(rules, strategy, &mut rng));
+        }
+    }
+    traces
+}
+
+const MAGIC: &str = "orchard-trace 1";
+
+/// Writes runs of traced games to a trace file.
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W) -> io::Result<TraceWriter<W>> {
+        writeln!(out, "{MAGIC}")?;
+        Ok(TraceWriter { out })
+    }
+
+    /// Starts a run of games played by `rules`, with a free-form note about where they came from.
+    pub fn run(&mut self, rules: &Rules, note: &str) -> io::Result<()> {
+        for line in note.lines() {
+            writeln!(self.out, "# {line}")?;
+        }
+        let Rules {
+            colors,
+            apples,
+            bird_faces,
+            basket_faces,
+            basket_picks,
+            track_length,
+            face_weights,
+        } = rules;
+        write!(
+            self.out,
+            "rules colors={colors} apples={apples} bird_faces={bird_faces} \
+             basket_faces={basket_faces} basket_picks={basket_picks} track_length={track_length}"
+        )?;
+        if !face_weights.is_empty() {
+            let weights: Vec<_> = face_weights.iter().map(u32::to_string).collect();
+            write!(self.out, " face_weights={}", weights.join(","))?;
+        }
+        writeln!(self.out)
+    }
+
+    pub fn game(&mut self, trace: &Trace) -> io::Result<()> {
+        writeln!(self.out, "{}", trace.encode())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// A run of games read back from a trace file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRun {
+    pub rules: Rules,
+    /// The comment lines before the rules.
+    pub notes: Vec<String>,
+    /// Every game, with the line of the file it came from.
+    pub games: Vec<(usize, Recorded)>,
+}
+
+fn parse_rules(line: &str) -> Result<Rules, String> {
+    let mut rules = Rules::default();
+    let mut seen = 0;
+    for field in line.split_whitespace().skip(1) {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, found '{field}'"))?;
+        // Only a die that isn't fair has face weights.
+        if key == "face_weights" {
+            rules.face_weights = value
+                .split(',')
+                .map(|weight| weight.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("bad value for {key}: '{value}'"))?;
+            continue;
+        }
+        let value = value
+            .parse()
+            .map_err(|_| format!("bad value for {key}: '{value}'"))?;
+        *match key {
+            "colors" => &mut rules.colors,
+            "apples" => &mut rules.apples,
+            "bird_faces" => &mut rules.bird_faces,
+            "basket_faces" => &mut rules.basket_faces,
+            "basket_picks" => &mut rules.basket_picks,
+            "track_length" => &mut rules.track_length,
+            _ => return Err(format!("unknown rule '{key}'")),
+        } = value;
+        seen += 1;
+    }
+    if seen != 6 {
+        return Err("the rules need all six of their values".to_owned());
+    }
+    rules.validate()?;
+    Ok(rules)
+}
+
+/// Reads a trace file; anything malformed is an `InvalidData` error giving the line it was on.
+pub fn read_traces(input: impl BufRead) -> io::Result<Vec<TraceRun>> {
+    let mut runs: Vec<TraceRun> = Vec::new();
+    let mut notes = Vec::new();
+    let mut lines = input.lines();
+    // An empty file is no more a trace than any other, or a truncated one would replay cleanly.
+    if lines.next().transpose()?.as_deref() != Some(MAGIC) {
+        let message = "line 1: not a trace file";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    for (number, line) in (2..).zip(lines) {
+        let line = line?;
+        let error = |message: String| {
+            let message = format!("line {number}: {message}");
+            Err(io::Error::new(io::ErrorKind::InvalidData, message))
+        };
+        if let Some(note) = line.strip_prefix('#') {
+            notes.push(note.trim().to_owned());
+        } else if line.starts_with("rules") {
+            match parse_rules(&line) {
+                Ok(rules) => runs.push(TraceRun {
+                    rules,
+                    notes: std::mem::take(&mut notes),
+                    games: Vec::new(),
+                }),
+                Err(message) => return error(message),
+            }
+        } else if !line.trim().is_empty() {
+            let Some(run) = runs.last_mut() else {
+                return error("a game before any rules".to_owned());
+            };
+            match Recorded::decode(&line) {
+                Ok(game) => run.games.push((number, game)),
+                Err(message) => return error(message),
+            }
+        }
+    }
+    Ok(runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+    use crate::simulate::{play_games, Tally};
+    use crate::strategy::{LargestFirst, Random};
+
+    #[test]
+    fn traces_are_the_simulated_games() {
+        let rules = Rules::orchard();
+        let n = CHUNK_GAMES + 100;
+        let traces = trace_games(&rules, &Random, n, 13);
+        let tally = traces.iter().fold(Tally::default(), |tally, trace| {
+            tally.record((trace.outcome, trace.turns()))
+        });
+        let simulated = play_games(&rules, &Random, 0..n, 13, &Silent);
+        assert_eq!(tally, simulated);
+    }
+
+    #[test]
+    fn traces_round_trip_through_files() {
+        let rules = Rules {
+            face_weights: vec![1, 1, 1, 2, 1, 3],
+            ..Rules::orchard()
+        };
+        let traces = trace_games(&rules, &Random, 50, 1);
+        let mut writer = TraceWriter::new(Vec::new()).unwrap();
+        writer.run(&rules, "classic\nrandom").unwrap();
+        for trace in &traces {
+            writer.game(trace).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let runs = read_traces(&file[..]).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].rules, rules);
+        assert_eq!(runs[0].notes, ["classic", "random"]);
+        for ((_, recorded), trace) in runs[0].games.iter().zip(&traces) {
+            assert_eq!(recorded.replay(&rules).as_ref(), Ok(trace));
+        }
+    }
+
+    #[test]
+    fn replay_catches_bad_traces() {
+        let rules = Rules::default();
+        let trace = trace_games(&rules, &LargestFirst, 1, 2).remove(0);
+        let line = trace.encode();
+        assert!(Recorded::decode(&line).unwrap().replay(&rules).is_ok());
+
+        let mut flipped = line.clone();
+        flipped.replace_range(0..1, if line.starts_with('W') { "L" } else { "W" });
+        assert!(Recorded::decode(&flipped).unwrap().replay(&rules).is_err());
+        let longer = format!("{line}B");
+        assert!(Recorded::decode(&longer).unwrap().replay(&rules).is_err());
+        let shorter = &line[..line.len() - 1];
+        assert!(Recorded::decode(shorter).unwrap().replay(&rules).is_err());
+
+        let harder = Rules {
+            track_length: 1,
+            ..rules.clone()
+        };
+        let replayed = Recorded::decode(&line).unwrap().replay(&harder);
+        assert!(replayed.is_err() || trace.turns() == 1);
+        assert!(Recorded::decode("W 1 0,0 K")
+            .unwrap()
+            .replay(&rules)
+            .is_err());
+        assert!(Recorded::decode("W 1 0,0 1a").is_err());
+
+        assert!(read_traces(&b""[..]).is_err());
+        assert!(read_traces(&b"rules colors=4\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! Win rate estimators that get more precision out of each simulated game than plain sampling.
+//!
+//! Each one plays games from the same reproducible chunks as `simulate`, and reports the variance
+//! it achieves per game so it can be weighed against the `p (1 - p)` of counting wins:
+//!
+//! * Antithetic: games are played in pairs, the second reading the first one's die rolls through
+//!   a mirror that swaps bad faces for good ones (birds for baskets), so that an unlucky game is
+//!   paired with a lucky one.
+//! * Control variate: alongside each game, the number of birds in its first few rolls is an
+//!   exactly known binomial, and how far it strays from its mean corrects the win count.
+//! * Conditional: once only a few fruit are left, the game stops and the exact solver gives its
+//!   win probability, integrating out the final rolls instead of sampling them.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+
+use num_traits::ToPrimitive;
+use rand::Rng;
+
+use crate::progress::Progress;
+use crate::rules::Rules;
+use crate::simulate::play_chunks;
+use crate::solver::Solver;
+use crate::stats::Interval;
+use crate::strategy::BasketStrategy;
+use crate::{Game, Outcome};
+
+/// How to turn simulated games into an estimate of the win rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Estimator {
+    /// The fraction of games won.
+    Plain,
+    /// The fraction won over pairs of games with mirrored die rolls.
+    Antithetic,
+    /// The fraction won, corrected by how many birds came up early on.
+    ControlVariate,
+    /// The mean exact win probability once `exact_fruit` fruit or fewer are left.
+    Conditional { exact_fruit: u8 },
+}
+
+/// A win rate estimate, and how precise the estimator that made it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub estimator: Estimator,
+    pub games: u64,
+    pub win_rate: f64,
+    /// The estimator's variance times the number of games it played, so that its standard error
+    /// is `sqrt(variance / games)`; plain sampling's is `win_rate * (1 - win_rate)`.
+    pub variance: f64,
+}
+
+impl Estimate {
+    pub fn standard_error(&self) -> f64 {
+        (self.variance / self.games as f64).sqrt()
+    }
+
+    pub fn interval(&self, confidence: f64) -> Interval {
+        Interval::normal(self.win_rate, self.standard_error(), confidence)
+    }
+
+    /// How many times more games plain sampling would need for the same precision, unless the
+    /// estimate has no variance at all (a game that's always won, say), and so is exact.
+    pub fn variance_reduction(&self) -> Option<f64> {
+        (self.variance > 0.0).then(|| self.win_rate * (1.0 - self.win_rate) / self.variance)
+    }
+}
+
+/// How many times each result came up; counting (rather than summing floats) keeps seeded runs
+/// identical however the chunks are spread across threads.
+type Counts<K> = HashMap<K, u64>;
+
+fn count<K: Eq + Hash>(mut counts: Counts<K>, key: K) -> Counts<K> {
+    *counts.entry(key).or_default() += 1;
+    counts
+}
+
+fn merge<K: Eq + Hash>(mut counts: Counts<K>, other: Counts<K>) -> Counts<K> {
+    for (key, n) in other {
+        *counts.entry(key).or_default() += n;
+    }
+    counts
+}
+
+/// Sample count, mean and variance of values that each came up some number of times.
+fn moments(samples: &[(f64, u64)]) -> (u64, f64, f64) {
+    let n: u64 = samples.iter().map(|&(_, count)| count).sum();
+    let mean = samples
+        .iter()
+        .map(|&(x, count)| x * count as f64)
+        .sum::<f64>()
+        / n as f64;
+    let variance = samples
+        .iter()
+        .map(|&(x, count)| (x - mean).powi(2) * count as f64)
+        .sum::<f64>()
+        / n as f64;
+    (n, mean, variance)
+}
+
+/// Plays a game on the draws in `draws` (see `ranked_face`), each read from the other end of the
+/// die if `mirrored`, drawing (and remembering) more whenever it runs out.
+fn play_draws(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    draws: &mut Vec<u32>,
+    mirrored: bool,
+    rng: &mut impl Rng,
+) -> Outcome {
+    let total_weight = rules.total_weight();
+    let mut game = Game::new(rules);
+    for turn in 0.. {
+        if turn == draws.len() {
+            draws.push(rng.gen_range(0..total_weight));
+        }
+        let draw = if mirrored {
+            total_weight - 1 - draws[turn]
+        } else {
+            draws[turn]
+        };
+        let roll = rules.face(ranked_face(rules, draw));
+        if let Some(outcome) = game.apply(rules, roll, strategy, rng) {
+            return outcome;
+        }
+    }
+    unreachable!("every game ends")
+}
+
+/// The face a draw from `0..rules.total_weight()` lands on, with the faces laid end to end, each
+/// as long as its weight, from worst to best: birds, then fruit, then baskets. A draw and its
+/// mirror image, as far from the other end, swap bad rolls for good ones.
+fn ranked_face(rules: &Rules, mut draw: u32) -> u8 {
+    let baskets = rules.colors + rules.basket_faces;
+    let ranked = (baskets..rules.faces())
+        .chain(0..rules.colors)
+        .chain(rules.colors..baskets);
+    for face in ranked {
+        if draw < rules.weight(face) {
+            return face;
+        }
+        draw -= rules.weight(face);
+    }
+    unreachable!("draws are less than the total weight")
+}
+
+/// The weight of every bird face added up, i.e. how many of the lowest draws are birds.
+fn bird_weight(rules: &Rules) -> u32 {
+    (rules.colors + rules.basket_faces..rules.faces())
+        .map(|face| rules.weight(face))
+        .sum()
+}
+
+/// Rolls the control variate watches: about as many as it takes to pick every fruit, if no roll
+/// were wasted on an empty tree.
+fn control_rolls(rules: &Rules) -> usize {
+    let fruit = f64::from(rules.colors) * f64::from(rules.apples);
+    let weight =
+        |faces: Range<u8>| -> f64 { faces.map(|face| f64::from(rules.weight(face))).sum() };
+    let baskets = rules.colors + rules.basket_faces;
+    let picked_per_roll = (weight(0..rules.colors)
+        + weight(rules.colors..baskets) * f64::from(rules.basket_picks))
+        / f64::from(rules.total_weight());
+    (fruit / picked_per_roll).round() as usize
+}
+
+/// Probability that the bird comes up fewer than `track_length` times in `rolls` rolls.
+fn bird_stays_out(rules: &Rules, rolls: usize) -> f64 {
+    let p = f64::from(bird_weight(rules)) / f64::from(rules.total_weight());
+    let mut term = (1.0 - p).powi(rolls as i32);
+    let mut total = 0.0;
+    for birds in 0..usize::from(rules.track_length).min(rolls + 1) {
+        total += term;
+        term *= (rolls - birds) as f64 / (birds + 1) as f64 * p / (1.0 - p);
+    }
+    total
+}
+
+/// Estimates the win rate from the games numbered `games`, like `simulate::play_games`.
+///
+/// The antithetic estimator plays each game twice, once mirrored, so it plays twice as many
+/// games in all.
+pub fn estimate(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    estimator: Estimator,
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+) -> Estimate {
+    let (samples, scale) = match estimator {
+        Estimator::Plain => {
+            let counts = play_chunks(
+                games,
+                seed,
+                progress,
+                |counts, rng| count(counts, Game::full_game(rules, strategy, rng).0),
+                merge,
+            );
+            let mut samples: Vec<_> = counts
+                .into_iter()
+                .map(|(outcome, n)| (f64::from(u8::from(outcome == Outcome::Won)), n))
+                .collect();
+            samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+            (samples, 1.0)
+        }
+        Estimator::Antithetic => {
+            let counts = play_chunks(
+                games,
+                seed,
+                progress,
+                |counts, rng| {
+                    let mut draws = Vec::new();
+                    let wins = [false, true]
+                        .into_iter()
+                        .filter(|&mirrored| {
+                            play_draws(rules, strategy, &mut draws, mirrored, rng) == Outcome::Won
+                        })
+                        .count();
+                    count(counts, wins)
+                },
+                merge,
+            );
+            let mut samples: Vec<_> = counts
+                .into_iter()
+                .map(|(wins, n)| (wins as f64 / 2.0, n))
+                .collect();
+            samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+            // Each sample took two games.
+            (samples, 2.0)
+        }
+        Estimator::ControlVariate => {
+            return control_variate(rules, strategy, games, seed, progress)
+        }
+        Estimator::Conditional { exact_fruit } => {
+            let counts = play_chunks(
+                games,
+                seed,
+                progress,
+                |counts, rng| {
+                    let mut game = Game::new(rules);
+                    while game.outcome().is_none()
+                        && game.orchards.iter().map(|&n| u32::from(n)).sum::<u32>()
+                            > u32::from(exact_fruit)
+                    {
+                        game.apply(rules, rng.sample(rules), strategy, rng);
+                    }
+                    count(counts, game)
+                },
+                merge,
+            );
+            let mut solver = Solver::new(rules, strategy);
+            let mut stops: Vec<_> = counts.into_iter().collect();
+            stops.sort_by(|(a, _), (b, _)| {
+                (a.bird_position, &a.orchards[..]).cmp(&(b.bird_position, &b.orchards[..]))
+            });
+            let samples = stops
+                .into_iter()
+                .map(|(game, n)| {
+                    let win_probability = match game.outcome() {
+                        Some(outcome) => f64::from(u8::from(outcome == Outcome::Won)),
+                        None => {
+                            // Unwrap: a probability always fits in an f64.
+                            solver.win_probability(game).to_f64().unwrap()
+                        }
+                    };
+                    (win_probability, n)
+                })
+                .collect();
+            (samples, 1.0)
+        }
+    };
+    let (n, mean, variance) = moments(&samples);
+    Estimate {
+        estimator,
+        games: n * scale as u64,
+        win_rate: mean,
+        variance: variance * scale,
+    }
+}
+
+fn control_variate(
+    rules: &Rules,
+    strategy: &dyn BasketStrategy,
+    games: Range<u64>,
+    seed: u64,
+    progress: &dyn Progress,
+) -> Estimate {
+    let rolls = control_rolls(rules);
+    let bird_weight = bird_weight(rules);
+    let counts = play_chunks(
+        games,
+        seed,
+        progress,
+        |counts, rng| {
+            let mut draws = Vec::with_capacity(rolls);
+            let won = play_draws(rules, strategy, &mut draws, false, rng) == Outcome::Won;
+            // The control needs every roll it watches, even after the game is over.
+            while draws.len() < rolls {
+                draws.push(rng.gen_range(0..rules.total_weight()));
+            }
+            let birds = draws[..rolls]
+                .iter()
+                .filter(|&&draw| draw < bird_weight)
+                .count();
+            count(counts, (won, birds < usize::from(rules.track_length)))
+        },
+        merge,
+    );
+
+    let n = |won: bool, control: bool| counts.get(&(won, control)).copied().unwrap_or(0) as f64;
+    let games = counts.values().sum::<u64>();
+    let total = games as f64;
+    let won = (n(true, false) + n(true, true)) / total;
+    let control = (n(false, true) + n(true, true)) / total;
+    let covariance = n(true, true) / total - won * control;
+    let control_variance = control * (1.0 - control);
+    // The best coefficient is estimated from the same games, which biases the estimate by O(1/n).
+    let beta = if control_variance > 0.0 {
+        covariance / control_variance
+    } else {
+        0.0
+    };
+    let variance = won * (1.0 - won) - beta * covariance;
+    Estimate {
+        estimator: Estimator::ControlVariate,
+        games,
+        win_rate: won - beta * (control - bird_stays_out(rules, rolls)),
+        variance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+    use crate::strategy::{LargestFirst, Random};
+
+    #[test]
+    fn mirror_swaps_birds_and_baskets() {
+        // Each face's mirror image, for a fair die.
+        let mirror = |rules: &Rules| {
+            let mut mirror = vec![0; usize::from(rules.faces())];
+            for draw in 0..rules.total_weight() {
+                let opposite = rules.total_weight() - 1 - draw;
+                mirror[usize::from(ranked_face(rules, draw))] = ranked_face(rules, opposite);
+            }
+            mirror
+        };
+        let rules = Rules::default();
+        assert_eq!(mirror(&rules), [3, 2, 1, 0, 5, 4]);
+        let rules = Rules {
+            bird_faces: 2,
+            ..Rules::default()
+        };
+        // Birds at 5 and 6 swap with the basket at 4 and the last fruit, and green stays put.
+        assert_eq!(mirror(&rules), [2, 1, 0, 6, 5, 4, 3]);
+
+        // A bird three times as likely as any other face takes up three draws, and mirrors to the
+        // basket, then the last two fruit.
+        let rules = Rules {
+            face_weights: vec![1, 1, 1, 1, 1, 3],
+            ..Rules::default()
+        };
+        let pairs: Vec<_> = (0..4)
+            .map(|draw| (ranked_face(&rules, draw), ranked_face(&rules, 7 - draw)))
+            .collect();
+        assert_eq!(pairs, [(5, 4), (5, 3), (5, 2), (0, 1)]);
+    }
+
+    #[test]
+    fn bird_race_probability() {
+        let rules = Rules {
+            track_length: 2,
+            ..Rules::default()
+        };
+        // No more than one bird in three rolls: (5/6)^3 + 3 (1/6) (5/6)^2.
+        let expected = (125.0 + 75.0) / 216.0;
+        assert!((bird_stays_out(&rules, 3) - expected).abs() < 1e-12);
+        assert_eq!(bird_stays_out(&rules, 1), 1.0);
+    }
+
+    #[test]
+    fn estimators_agree_with_exact() {
+        let rules = Rules::default();
+        let estimators = [
+            Estimator::Plain,
+            Estimator::Antithetic,
+            Estimator::ControlVariate,
+            Estimator::Conditional { exact_fruit: 6 },
+        ];
+        let strategies: [&dyn BasketStrategy; 2] = [&LargestFirst, &Random];
+        for strategy in strategies {
+            let exact = Solver::new(&rules, strategy).solve().win_rate();
+            for estimator in estimators {
+                let estimate = estimate(&rules, strategy, estimator, 0..40_000, 3, &Silent);
+                assert!(
+                    (estimate.win_rate - exact).abs() < 4.0 * estimate.standard_error(),
+                    "{estimate:?}, exact {exact}"
+                );
+                if estimator != Estimator::Plain {
+                    let reduction = estimate.variance_reduction().unwrap();
+                    assert!(reduction > 1.0, "{estimate:?}");
+                }
+            }
+        }
+
+        // Without a bird, every game is won, and nothing varies.
+        let birdless = Rules {
+            bird_faces: 0,
+            ..rules
+        };
+        let estimate = estimate(
+            &birdless,
+            &LargestFirst,
+            Estimator::ControlVariate,
+            0..1000,
+            3,
+            &Silent,
+        );
+        assert_eq!(estimate.win_rate, 1.0);
+        assert_eq!(estimate.variance_reduction(), None);
+    }
+}